@@ -2,23 +2,72 @@
 use crate::config;
 use quiche::h3::NameValue;
 use rand::RngCore;
-use std::{collections::HashMap, net::SocketAddr};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+pub mod altsvc;
+pub mod close;
+pub mod download;
 pub mod http;
+pub mod masque;
+pub mod tls;
+
+pub use close::CloseReason;
+
+// Maximum number of datagrams handed to the kernel per send syscall
+const SEND_BATCH: usize = 32;
+
+// Bytes read from a response body per recv_body call. Scratch buffers live on the Client and
+// are reused for every event, so a bigger chunk costs a fixed amount of memory per connection
+// and saves calls on large bodies
+const BODY_CHUNK: usize = 16 * 1024;
+
+// Largest packet of a connection tunneled through a proxy. QUIC's minimum, which leaves room
+// for the proxy connection's own overhead within a common MTU
+const TUNNEL_MTU: usize = 1200;
+
+// Datagrams quiche queues in each direction when --datagram enables them
+const DGRAM_QUEUE_LEN: usize = 100;
+
+// ECN codepoint ECT(0) (RFC 3168), the low two bits of the TOS or traffic class byte
+const ECT0: i32 = 0b10;
+
+// HTTP/3 error code for closing a connection which is no longer needed
+pub(crate) const H3_NO_ERROR: u64 = 0x100;
 
 pub struct Client {
+    args: config::QuicConfig,          // kept to reconnect after GOAWAY
+    draining: bool,                    // server sent GOAWAY, no new requests on this connection
     in_flight: HashMap<u64, InFlight>, // maps stream_id to sent request
     conn_quic: quiche::Connection,
     conn_h3: Option<quiche::h3::Connection>,
     socket: mio::net::UdpSocket,
+    old_socket: Option<mio::net::UdpSocket>, // left by a migration, still read for late packets
     poll: mio::Poll,
     events: mio::Events,
+    buf: Vec<u8>,                     // receive buffer sized to the configured MTU
+    out: Vec<u8>,                     // fits SEND_BATCH datagrams
+    unsent: Vec<(usize, SocketAddr)>, // refused by the full socket buffer, kept at the start of `out`
+    gso: bool,                        // kernel supports UDP segmentation offload
+    body_buf: Vec<u8>,                // BODY_CHUNK sized scratch for reading response bodies
+    tunnel: Option<masque::Tunnel>,   // packets go through a MASQUE proxy instead of the socket
+    session_saved: bool,              // a ticket of this connection was written to the cache
+    ecn: Option<EcnCounts>,           // marks of received packets, None unless ECN is enabled
 }
 
 impl Client {
     pub fn new(args: config::QuicConfig) -> anyhow::Result<Self> {
         // initialize udp socket
-        let mut socket = mio::net::UdpSocket::bind("0.0.0.0:0".parse().unwrap())?;
+        let bind = args
+            .bind
+            .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+        let mut socket =
+            mio::net::UdpSocket::bind(bind).map_err(|source| BindError { addr: bind, source })?;
 
         // setup event loop using mio
         let mut poll = mio::Poll::new()?;
@@ -32,21 +81,51 @@ impl Client {
             )
             .unwrap();
 
+        let ecn = match args.ecn.then(|| enable_ecn(&socket)) {
+            Some(Ok(())) => Some(EcnCounts::default()),
+            Some(Err(e)) => {
+                log::warn!("failed to enable ECN on the socket, continuing without: {e}");
+                None
+            }
+            None => None,
+        };
+
+        let tunnel = match &args.proxy {
+            Some(proxy) => Some(masque::Tunnel::open(
+                proxy,
+                &args,
+                &socket,
+                &mut poll,
+                &mut events,
+            )?),
+            None => None,
+        };
+        let mtu = if tunnel.is_some() {
+            TUNNEL_MTU
+        } else {
+            args.mtu
+        };
+
         // setup QUIC config
-        let mut config_quic = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+        let mut config_quic = quiche::Config::new(args.quic_version)?;
 
         config_quic.verify_peer(args.verify_peer);
         config_quic.set_application_protos(quiche::h3::APPLICATION_PROTOCOL)?;
-        config_quic.set_max_recv_udp_payload_size(config::MAX_DATAGRAM_SIZE);
-        config_quic.set_max_send_udp_payload_size(config::MAX_DATAGRAM_SIZE);
-        config_quic.set_initial_max_data(10_000_000);
+        config_quic.set_max_recv_udp_payload_size(mtu);
+        config_quic.set_max_send_udp_payload_size(mtu);
+        config_quic.set_initial_max_data(args.max_data);
         config_quic.set_initial_max_stream_data_bidi_local(1_000_000);
         config_quic.set_initial_max_stream_data_bidi_remote(1_000_000);
         config_quic.set_initial_max_stream_data_uni(1_000_000);
-        config_quic.set_initial_max_streams_bidi(100);
+        config_quic.set_initial_max_streams_bidi(args.max_streams);
         config_quic.set_initial_max_streams_uni(100);
-        config_quic.set_disable_active_migration(true);
-        config_quic.set_max_idle_timeout(5000);
+        config_quic.set_disable_active_migration(!args.allow_migration);
+        config_quic.set_cc_algorithm(args.cc.to_quiche());
+        config_quic.set_max_idle_timeout(args.idle_timeout.as_millis() as u64);
+        config_quic.set_max_ack_delay(args.max_ack_delay);
+        if args.datagram {
+            config_quic.enable_dgram(true, DGRAM_QUEUE_LEN, DGRAM_QUEUE_LEN);
+        }
 
         // determine SCID
         let mut scid_bytes = [0u8; quiche::MAX_CONN_ID_LEN];
@@ -59,10 +138,11 @@ impl Client {
         let local = socket.local_addr()?;
 
         log::info!(
-            "connecting to {:} from {:?} with scid {}",
+            "connecting to {:} from {:?} with scid {}, congestion control {:?}",
             peer,
             &socket.local_addr()?,
-            hex_dump(&scid)
+            hex_dump(&scid),
+            args.cc
         );
 
         // establish quic connection
@@ -73,69 +153,120 @@ impl Client {
             peer,
             &mut config_quic,
         )?;
-
-        // perform handshake
-        Self::perform_handshake(&mut conn_quic, &mut socket, &mut poll, &mut events)?;
-
-        log::info!(
-            "quic connection established? {}",
-            conn_quic.is_established()
-        );
+        let cached = match &args.session_file {
+            Some(path) => load_session(&mut conn_quic, path),
+            None => false,
+        };
 
         let in_flight: HashMap<u64, InFlight> = HashMap::new();
 
-        Ok(Self {
+        let mut client = Self {
+            buf: vec![0; args.mtu],
+            out: vec![0; args.mtu * SEND_BATCH],
+            unsent: Vec::new(),
+            gso: supports_gso(&socket),
+            body_buf: vec![0; BODY_CHUNK],
+            args,
+            draining: false,
             poll,
             events,
             socket,
+            old_socket: None,
             conn_quic,
             conn_h3: None,
             in_flight,
-        })
+            tunnel,
+            session_saved: false,
+            ecn,
+        };
+
+        if let Err(e) = client.perform_handshake() {
+            // the server may have rotated its ticket keys, don't offer the ticket again
+            if let Some(path) = client.args.session_file.as_ref().filter(|_| cached) {
+                log::info!("dropping cached session ticket {}", path.display());
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(e);
+        }
+
+        log::info!(
+            "quic connection established? {}, resumed? {}",
+            client.conn_quic.is_established(),
+            client.conn_quic.is_resumed()
+        );
+
+        if client.conn_quic.application_proto() != quiche::h3::APPLICATION_PROTOCOL[0] {
+            return Err(HandshakeError::AlpnRejected.into());
+        }
+
+        let info = client.connection_info();
+        log::info!(
+            "negotiated {} over QUIC version {:#x}{}",
+            info.alpn,
+            info.version,
+            info.negotiation()
+        );
+
+        if client.args.allow_migration {
+            client.issue_scids()?;
+        }
+
+        Ok(client)
     }
 
-    fn perform_handshake(
-        conn: &mut quiche::Connection,
-        socket: &mut mio::net::UdpSocket,
-        poll: &mut mio::Poll,
-        events: &mut mio::Events,
-    ) -> anyhow::Result<()> {
-        let mut buf = [0; config::MAX_DATAGRAM_SIZE];
-        let mut out = [0; config::MAX_DATAGRAM_SIZE];
+    pub fn connection_info(&self) -> ConnectionInfo {
+        let cert = self
+            .conn_quic
+            .peer_cert()
+            .and_then(|der| x509_parser::parse_x509_certificate(der).ok())
+            .map(|(_, cert)| (cert.subject().to_string(), cert.issuer().to_string()));
+        let (peer_subject, peer_issuer) = cert.unzip();
 
-        while !conn.is_established() {
-            loop {
-                match conn.send(&mut out) {
-                    Ok((write, send_info)) => match socket.send_to(&out[..write], send_info.to) {
-                        Ok(_) => {}
-                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
-                        Err(e) => return Err(e.into()),
-                    },
-                    Err(quiche::Error::Done) => break,
-                    Err(e) => return Err(e.into()),
-                }
-            }
+        ConnectionInfo {
+            alpn: String::from_utf8_lossy(self.conn_quic.application_proto()).into_owned(),
+            // quiche has no accessor for it, but it only speaks this version, so a completed
+            // handshake used it, after version negotiation when another one was offered
+            version: quiche::PROTOCOL_VERSION,
+            offered_version: self.args.quic_version,
+            cc: self.args.cc,
+            peer_subject,
+            peer_issuer,
+            resumed: self.conn_quic.is_resumed(),
+            max_ack_delay: self.args.max_ack_delay,
+            peer_max_ack_delay: self
+                .conn_quic
+                .peer_transport_params()
+                .map(|params| params.max_ack_delay),
+        }
+    }
 
-            poll.poll(events, conn.timeout())?;
+    fn perform_handshake(&mut self) -> anyhow::Result<()> {
+        while !self.conn_quic.is_established() {
+            self.flush_sends()?;
 
-            loop {
-                match socket.recv_from(&mut buf) {
-                    Ok((len, from)) => {
-                        let local = socket.local_addr()?;
-                        let recv_info = quiche::RecvInfo { from, to: local };
-                        conn.recv(&mut buf[..len], recv_info)?;
-                    }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
-                    Err(e) => return Err(e.into()),
+            // the proxy connection has timers of its own
+            let timeout = match (&self.tunnel, self.conn_quic.timeout()) {
+                (Some(tunnel), Some(timeout)) => tunnel.timeout().map(|t| t.min(timeout)),
+                (Some(tunnel), None) => tunnel.timeout(),
+                (None, timeout) => timeout,
+            };
+            self.poll.poll(&mut self.events, timeout)?;
+
+            // quiche closes the connection on e.g. a failed certificate check, the close
+            // frame says more than the error
+            if let Err(e) = self.recv_packets() {
+                if CloseReason::of(&self.conn_quic).is_some() {
+                    return Err(HandshakeError::from_closed(&self.conn_quic).into());
                 }
+                return Err(e);
             }
 
-            if events.is_empty() {
-                conn.on_timeout();
+            if self.events.is_empty() {
+                self.on_timeout();
             }
 
-            if conn.is_closed() {
-                return Err(anyhow::anyhow!("handshake failed"));
+            if self.conn_quic.is_closed() {
+                return Err(HandshakeError::from_closed(&self.conn_quic).into());
             }
         }
 
@@ -143,46 +274,158 @@ impl Client {
     }
 
     pub fn poll_io(&mut self) -> anyhow::Result<()> {
-        let local = self.socket.local_addr()?;
-        let mut buf = [0; config::MAX_DATAGRAM_SIZE];
-        let mut out = [0; config::MAX_DATAGRAM_SIZE];
+        self.exchange_packets()
+            .map_err(|e| match CloseReason::of(&self.conn_quic) {
+                Some(reason) => e.context(reason.to_string()),
+                None => e,
+            })
+    }
 
+    fn exchange_packets(&mut self) -> anyhow::Result<()> {
         // non-blocking poll
         self.poll
             .poll(&mut self.events, Some(std::time::Duration::from_millis(0)))?;
 
-        loop {
-            let (len, from) = match self.socket.recv_from(&mut buf) {
-                Ok(v) => v,
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
-                Err(e) => return Err(e.into()),
-            };
+        self.recv_packets()?;
 
-            let recv_info = quiche::RecvInfo { to: local, from };
-            self.conn_quic.recv(&mut buf[..len], recv_info)?;
+        if self.events.is_empty() {
+            self.on_timeout();
         }
 
-        if self.events.is_empty() {
-            self.conn_quic.on_timeout();
+        self.save_session();
+        self.log_path_events();
+        self.send_bodies();
+        self.flush_sends()
+    }
+
+    // Writes the connection's session ticket to the cache once the server sent one
+    fn save_session(&mut self) {
+        if self.session_saved {
+            return;
         }
+        let (Some(path), Some(session)) = (&self.args.session_file, self.conn_quic.session())
+        else {
+            return;
+        };
 
-        loop {
-            match self.conn_quic.send(&mut out) {
-                Ok((write, send_info)) => match self.socket.send_to(&out[..write], send_info.to) {
-                    Ok(_) => {}
+        self.session_saved = true;
+        match write_private(path, session) {
+            Ok(()) => log::info!("saved session ticket to {}", path.display()),
+            Err(e) => log::warn!("failed to save session ticket to {}: {e}", path.display()),
+        }
+    }
+
+    // Feeds the packets received since the last call to quiche
+    fn recv_packets(&mut self) -> anyhow::Result<()> {
+        if let Some(tunnel) = self.tunnel.as_mut() {
+            let local = self.socket.local_addr()?;
+            tunnel.recv_from(&self.socket)?;
+            if tunnel.is_closed() {
+                return Err(masque::TunnelError::Closed.into());
+            }
+
+            let from = SocketAddr::V4(self.args.remote_addr);
+            while let Some(len) = tunnel.recv(&mut self.buf) {
+                let recv_info = quiche::RecvInfo { to: local, from };
+                match self.conn_quic.recv(&mut self.buf[..len], recv_info) {
+                    // late packets of a closed connection
+                    Ok(_) | Err(quiche::Error::Done) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            return Ok(());
+        }
+
+        for socket in std::iter::once(&self.socket).chain(&self.old_socket) {
+            let local = socket.local_addr()?;
+            loop {
+                let received = match self.ecn.as_mut() {
+                    Some(counts) => recv_with_ecn(socket, &mut self.buf).map(|(len, from, ecn)| {
+                        counts.record(ecn);
+                        (len, from)
+                    }),
+                    None => socket.recv_from(&mut self.buf),
+                };
+                let (len, from) = match received {
+                    Ok(v) => v,
                     Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
                     Err(e) => return Err(e.into()),
-                },
-                Err(quiche::Error::Done) => break,
-                Err(e) => return Err(e.into()),
+                };
+
+                let recv_info = quiche::RecvInfo { to: local, from };
+                match self.conn_quic.recv(&mut self.buf[..len], recv_info) {
+                    Ok(_) | Err(quiche::Error::Done) => {}
+                    Err(e) => return Err(e.into()),
+                }
             }
         }
+
         Ok(())
     }
 
-    fn ensure_h3(&mut self) -> anyhow::Result<()> {
+    fn on_timeout(&mut self) {
+        self.conn_quic.on_timeout();
+        if let Some(tunnel) = self.tunnel.as_mut() {
+            tunnel.on_timeout();
+        }
+    }
+
+    // Sends the datagrams quiche has queued, up to SEND_BATCH of them per syscall
+    fn flush_sends(&mut self) -> anyhow::Result<()> {
+        let mtu = self.args.mtu;
+
+        if let Some(tunnel) = self.tunnel.as_mut() {
+            loop {
+                match self.conn_quic.send(&mut self.out[..TUNNEL_MTU]) {
+                    Ok((write, _)) => tunnel.send(&self.out[..write])?,
+                    Err(quiche::Error::Done) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            return tunnel.flush(&self.socket);
+        }
+
+        loop {
+            // the ones the socket refused last time go first, quiche already counts them as sent
+            let mut packets = std::mem::take(&mut self.unsent);
+            packets.reserve(SEND_BATCH - packets.len());
+
+            while packets.len() < SEND_BATCH {
+                let start = packets.len() * mtu;
+                match self.conn_quic.send(&mut self.out[start..start + mtu]) {
+                    Ok((write, send_info)) => packets.push((write, send_info.to)),
+                    Err(quiche::Error::Done) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            if packets.is_empty() {
+                return Ok(());
+            }
+
+            let sent = send_batch(&self.socket, &self.out, mtu, &packets, &mut self.gso)?;
+            if sent < packets.len() {
+                // the socket buffer is full, the rest waits for the next writable event
+                self.out.copy_within(sent * mtu..packets.len() * mtu, 0);
+                self.unsent = packets.split_off(sent);
+                return Ok(());
+            }
+
+            if packets.len() < SEND_BATCH {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn ensure_h3(&mut self) -> anyhow::Result<()> {
         if self.conn_h3.is_none() {
-            let h3_config = quiche::h3::Config::new()?;
+            let mut h3_config = quiche::h3::Config::new()?;
+            if let Some(capacity) = self.args.qpack_max_table {
+                h3_config.set_qpack_max_table_capacity(capacity);
+            }
+            if let Some(blocked) = self.args.qpack_blocked {
+                h3_config.set_qpack_blocked_streams(blocked);
+            }
             self.conn_h3 = Some(quiche::h3::Connection::with_transport(
                 &mut self.conn_quic,
                 &h3_config,
@@ -191,7 +434,121 @@ impl Client {
         Ok(())
     }
 
+    // Sends CONNECTION_CLOSE so the server can free the connection right away instead of
+    // waiting for the idle timeout
+    pub fn close(&mut self) -> anyhow::Result<()> {
+        match self.conn_quic.close(true, H3_NO_ERROR, b"") {
+            // already closed
+            Ok(()) | Err(quiche::Error::Done) => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.flush_sends()?;
+
+        if let Some(tunnel) = self.tunnel.as_mut() {
+            tunnel.close(&self.socket)?;
+        }
+        Ok(())
+    }
+
+    // Hands the server spare connection IDs up to its limit, a migrated path needs one of its own
+    fn issue_scids(&mut self) -> anyhow::Result<()> {
+        while self.conn_quic.scids_left() > 0 {
+            let mut scid = [0u8; quiche::MAX_CONN_ID_LEN];
+            rand::rng().fill_bytes(&mut scid);
+            let reset_token = rand::random::<u128>();
+            self.conn_quic
+                .new_scid(&quiche::ConnectionId::from_ref(&scid), reset_token, false)?;
+        }
+        Ok(())
+    }
+
+    // Moves the connection to a new local port, as a client changing networks would. Needs
+    // QuicConfig::allow_migration and a spare connection ID from the server
+    pub fn migrate(&mut self) -> anyhow::Result<()> {
+        if self.tunnel.is_some() {
+            anyhow::bail!("can't migrate a connection tunneled through a proxy");
+        }
+        if self.conn_quic.available_dcids() == 0 {
+            anyhow::bail!("server issued no spare connection IDs");
+        }
+
+        let old = self.socket.local_addr()?;
+        let bind = SocketAddr::new(old.ip(), 0);
+        let mut socket =
+            mio::net::UdpSocket::bind(bind).map_err(|source| BindError { addr: bind, source })?;
+        let local = socket.local_addr()?;
+        if self.ecn.is_some() {
+            enable_ecn(&socket)?;
+        }
+        self.conn_quic.migrate_source(local)?;
+        // the challenge also shows the server the new address, with every request already sent
+        // it would otherwise keep answering the old port
+        self.conn_quic
+            .probe_path(local, SocketAddr::V4(self.args.remote_addr))?;
+
+        self.poll.registry().register(
+            &mut socket,
+            mio::Token(0),
+            mio::Interest::READABLE | mio::Interest::WRITABLE,
+        )?;
+        self.gso = supports_gso(&socket);
+        // built for the old path, quiche recovers them as lost
+        self.unsent.clear();
+        // packets the server sent before it noticed the move still arrive at the old port, and
+        // quiche only retransmits their frames once they are known to be lost, which a path
+        // the server stopped using never tells it
+        if let Some(mut previous) = self
+            .old_socket
+            .replace(std::mem::replace(&mut self.socket, socket))
+        {
+            self.poll.registry().deregister(&mut previous)?;
+        }
+
+        log::info!("migrating connection from {old} to {local}");
+        Ok(())
+    }
+
+    // Logs what happened to the connection's paths, after a migration the new one is validated
+    fn log_path_events(&mut self) {
+        while let Some(event) = self.conn_quic.path_event_next() {
+            match event {
+                quiche::PathEvent::Validated(local, peer) => {
+                    log::info!("path {local} -> {peer} validated")
+                }
+                quiche::PathEvent::FailedValidation(local, peer) => {
+                    log::warn!("path {local} -> {peer} failed validation")
+                }
+                quiche::PathEvent::Closed(local, peer) => {
+                    log::info!("path {local} -> {peer} closed")
+                }
+                event => log::debug!("path event: {event:?}"),
+            }
+        }
+    }
+
+    // Replaces the connection with a freshly established one
+    pub fn reconnect(&mut self) -> anyhow::Result<()> {
+        log::info!("reconnecting to {}", self.args.remote_addr);
+
+        if let Err(e) = self.close() {
+            log::debug!("failed to close the old connection: {e:#}");
+        }
+
+        // a fixed local port is still held by the old socket
+        if self.args.bind.is_some_and(|addr| addr.port() != 0) {
+            self.poll.registry().deregister(&mut self.socket)?;
+            self.socket = mio::net::UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))?;
+        }
+
+        *self = Self::new(self.args.clone())?;
+        Ok(())
+    }
+
     pub fn send_request(&mut self, req: &http::Request) -> Result<u64, ClientError> {
+        if self.draining {
+            return Err(ClientError::Draining);
+        }
+
         self.ensure_h3().map_err(ClientError::Other)?;
 
         // Check if the peer allows new streams
@@ -201,7 +558,8 @@ impl Client {
 
         let h3 = self.conn_h3.as_mut().unwrap();
 
-        let stream_id = match h3.send_request(&mut self.conn_quic, &req.to_quiche(), true) {
+        let fin = req.body.is_none();
+        let stream_id = match h3.send_request(&mut self.conn_quic, &req.to_quiche(), fin) {
             Ok(id) => id,
 
             // Flow-control prevents sending right now
@@ -210,37 +568,92 @@ impl Client {
             Err(e) => return Err(ClientError::Other(e.into())),
         };
 
-        if self
-            .in_flight
-            .insert(stream_id, InFlight::new(&req.path))
-            .is_some()
-        {
+        let mut state = InFlight::new(&req.path);
+        state.unsent = req.body.clone();
+        if self.in_flight.insert(stream_id, state).is_some() {
             return Err(ClientError::Other(anyhow::anyhow!(
                 "stream_id {stream_id} already existed"
             )));
         }
+        self.send_bodies();
 
         Ok(stream_id)
     }
 
-    pub fn poll_responses(&mut self) -> anyhow::Result<Vec<http::Response>> {
+    // Writes as much of the request bodies as flow control allows, the rest is sent once the
+    // server extends its windows
+    fn send_bodies(&mut self) {
+        let Some(h3) = self.conn_h3.as_mut() else {
+            return;
+        };
+
+        for (id, state) in &mut self.in_flight {
+            let Some(body) = state.unsent.as_mut() else {
+                continue;
+            };
+
+            match h3.send_body(&mut self.conn_quic, *id, body, true) {
+                Ok(written) if written == body.len() => state.unsent = None,
+                Ok(written) => {
+                    body.drain(..written);
+                }
+                Err(quiche::h3::Error::Done) => {}
+                Err(e) => {
+                    log::debug!("failed to send request body on stream {id}: {e}");
+                    state.unsent = None;
+                }
+            }
+        }
+    }
+
+    // Returns streams completed since the last call, either as a response or as a per-stream
+    // failure which shouldn't abort the whole scan
+    pub fn poll_responses(&mut self) -> anyhow::Result<Vec<Result<http::Response, StreamFailure>>> {
         let Some(h3) = self.conn_h3.as_mut() else {
             return Ok(Vec::new());
         };
 
-        let mut completed: Vec<http::Response> = Vec::new();
+        let mut completed = Vec::new();
 
         loop {
             match h3.poll(&mut self.conn_quic) {
                 Ok((id, quiche::h3::Event::Headers { list, .. })) => {
-                    let state = self.in_flight.get_mut(&id).expect("unknown stream id");
+                    // streams reaped after a timeout may still receive events, and a buggy or
+                    // hostile server may send them for streams we never opened
+                    let Some(state) = self.in_flight.get_mut(&id) else {
+                        log::debug!("ignoring headers on unknown stream {id}");
+                        continue;
+                    };
+
+                    // a header block after the final status carries trailers
+                    let trailers = state.status.is_some_and(|status| status >= 200);
 
                     for h in list {
                         let name = String::from_utf8_lossy(h.name()).to_string();
                         let value = String::from_utf8_lossy(h.value()).to_string();
 
-                        if name == ":status" {
-                            state.status = Some(value.parse()?);
+                        let fields = if trailers {
+                            &state.trailers
+                        } else {
+                            &state.headers
+                        };
+                        // a hostile server may send any number of fields, only keep the first ones
+                        if let Some(max) = self.args.max_headers
+                            && fields.len() >= max
+                            && !fields.contains_key(&name)
+                            && name != ":status"
+                        {
+                            state.headers_truncated = true;
+                            continue;
+                        }
+
+                        if trailers {
+                            state.trailers.insert(name, value);
+                        } else if name == ":status" {
+                            match value.parse() {
+                                Ok(status) => state.status = Some(status),
+                                Err(_) => state.error = Some(StreamError::InvalidStatus(value)),
+                            }
                         } else {
                             state.headers.insert(name, value);
                         }
@@ -248,41 +661,487 @@ impl Client {
                 }
 
                 Ok((id, quiche::h3::Event::Data)) => {
-                    let state = self.in_flight.get_mut(&id).expect("unknown stream id");
+                    let Some(state) = self.in_flight.get_mut(&id) else {
+                        log::debug!("discarding data on unknown stream {id}");
+                        // drain it anyway, unread data holds on to connection flow control
+                        while h3
+                            .recv_body(&mut self.conn_quic, id, &mut self.body_buf)
+                            .is_ok()
+                        {}
+                        continue;
+                    };
+
+                    // keep reading past the size cap so flow control isn't stalled
+                    while let Ok(read) = h3.recv_body(&mut self.conn_quic, id, &mut self.body_buf) {
+                        state.received += read;
 
-                    let mut buf = [0; config::MAX_DATAGRAM_SIZE];
+                        let keep = match self.args.max_body_size {
+                            Some(max) => read.min(max.saturating_sub(state.kept())),
+                            None => read,
+                        };
+                        let chunk = &self.body_buf[..keep];
 
-                    while let Ok(read) = h3.recv_body(&mut self.conn_quic, id, &mut buf) {
-                        state.body.extend_from_slice(&buf[..read]);
+                        let Some(dir) = &self.args.stream_bodies else {
+                            state.body.extend_from_slice(chunk);
+                            continue;
+                        };
+                        if state.error.is_some() {
+                            continue;
+                        }
+                        // the connection id keeps names unique across reconnects
+                        let name = format!("{}_{id}", hex_dump(&self.conn_quic.source_id()[..8]));
+                        if let Err(e) = state.write_body_file(dir, &name, chunk) {
+                            state.error = Some(StreamError::BodyFile(e));
+                        }
                     }
                 }
 
                 Ok((id, quiche::h3::Event::Finished)) => {
-                    let state = self.in_flight.remove(&id).expect("unknown stream id");
-
-                    let status = state
-                        .status
-                        .ok_or_else(|| anyhow::anyhow!("missing :status"))?;
-
-                    completed.push(http::Response::new(
-                        &state.path,
-                        status,
-                        state.headers,
-                        state.body,
-                    ));
+                    let Some(state) = self.in_flight.remove(&id) else {
+                        log::debug!("ignoring end of unknown stream {id}");
+                        continue;
+                    };
+
+                    completed.push(state.into_response(id));
+                }
+
+                Ok((id, quiche::h3::Event::Reset(code))) => {
+                    if let Some(state) = self.in_flight.remove(&id) {
+                        completed.push(Err(state.into_failure(id, StreamError::Reset(code))));
+                    }
+                }
+
+                Ok((id, quiche::h3::Event::GoAway)) => {
+                    // streams with id >= GOAWAY id won't be processed by the server
+                    log::warn!("server sent GOAWAY with stream id {id}");
+                    self.draining = true;
+
+                    let dropped: Vec<u64> = self
+                        .in_flight
+                        .keys()
+                        .filter(|stream_id| **stream_id >= id)
+                        .copied()
+                        .collect();
+
+                    for stream_id in dropped {
+                        let state = self.in_flight.remove(&stream_id).unwrap();
+                        completed.push(Err(state.into_failure(stream_id, StreamError::GoAway)));
+                    }
                 }
 
+                // sent by clients only. quiche has no server push: it never sends MAX_PUSH_ID,
+                // so servers may not push, and drops PUSH_PROMISE frames without an event
+                Ok((_, quiche::h3::Event::PriorityUpdate)) => {}
+
                 Err(quiche::h3::Error::Done) => break,
                 Err(e) => return Err(e.into()),
-                _ => {}
             }
         }
         Ok(completed)
     }
 
+    // Removes streams which haven't completed within `timeout`
+    pub fn reap_timed_out(&mut self, timeout: Duration) -> Vec<StreamFailure> {
+        let expired: Vec<u64> = self
+            .in_flight
+            .iter()
+            .filter(|(_, state)| state.sent_at.elapsed() > timeout)
+            .map(|(id, _)| *id)
+            .collect();
+
+        expired
+            .into_iter()
+            .map(|id| {
+                // tell the server we're no longer interested in this stream
+                let _ = self
+                    .conn_quic
+                    .stream_shutdown(id, quiche::Shutdown::Read, 0);
+
+                let state = self.in_flight.remove(&id).unwrap();
+                state.into_failure(id, StreamError::TimedOut)
+            })
+            .collect()
+    }
+
+    // Sends a single request and waits for its response, used for probes before a scan
+    pub fn fetch(
+        &mut self,
+        req: &http::Request,
+        timeout: Duration,
+    ) -> anyhow::Result<http::Response> {
+        let started = Instant::now();
+        let mut stream_id = None;
+
+        while started.elapsed() < timeout {
+            self.poll_io()?;
+
+            if stream_id.is_none() {
+                match self.send_request(req) {
+                    Ok(id) => stream_id = Some(id),
+                    Err(ClientError::InFlightFull | ClientError::WouldBlock) => {}
+                    Err(e) => return Err(e.into()),
+                }
+                continue;
+            }
+
+            for completed in self.poll_responses()? {
+                match completed {
+                    Ok(resp) if Some(resp.stream_id) == stream_id => return Ok(resp),
+                    Err(failure) if Some(failure.stream_id) == stream_id => {
+                        anyhow::bail!("{}: {}", failure.path, failure.error)
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(id) = stream_id {
+            let _ = self
+                .conn_quic
+                .stream_shutdown(id, quiche::Shutdown::Read, 0);
+            self.in_flight.remove(&id);
+        }
+        anyhow::bail!("{}: {}", req.path, StreamError::TimedOut)
+    }
+
+    // Sends one HTTP datagram (RFC 9297) and waits up to `timeout` for any datagram back.
+    // Needs QuicConfig::datagram
+    pub fn probe_datagram(&mut self, timeout: Duration) -> anyhow::Result<DatagramProbe> {
+        let Some(max_len) = self.conn_quic.dgram_max_writable_len() else {
+            return Ok(DatagramProbe::default());
+        };
+
+        // quarter stream ID 0 ties it to the first request stream, the rest is opaque
+        let mut payload = vec![0];
+        payload.extend_from_slice(b"fuzzh3 datagram probe");
+        payload.truncate(max_len);
+
+        let started = Instant::now();
+        self.conn_quic.dgram_send(&payload)?;
+
+        let mut round_trip = None;
+        let mut buf = vec![0; self.buf.len()];
+        while round_trip.is_none() && started.elapsed() < timeout {
+            self.poll_io()?;
+            // processes SETTINGS, which tell whether the server takes HTTP datagrams
+            self.poll_responses()?;
+
+            match self.conn_quic.dgram_recv(&mut buf) {
+                Ok(len) => round_trip = Some((started.elapsed(), len)),
+                Err(quiche::Error::Done) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(DatagramProbe {
+            max_len: Some(max_len),
+            h3_datagram: self
+                .conn_h3
+                .as_ref()
+                .is_some_and(|h3| h3.dgram_enabled_by_peer(&self.conn_quic)),
+            round_trip,
+        })
+    }
+
+    // Host the connection was set up for, sent as SNI
+    pub fn server_name(&self) -> &str {
+        &self.args.server_name
+    }
+
+    // Transport counters of the current connection
+    pub fn stats(&self) -> quiche::Stats {
+        self.conn_quic.stats()
+    }
+
+    // Per path counters of the current connection, more than one path after a migration
+    pub fn path_stats(&self) -> Vec<quiche::PathStats> {
+        self.conn_quic.path_stats().collect()
+    }
+
+    // ECN marks of the packets received on the current connection, None unless ECN is enabled
+    pub fn ecn_counts(&self) -> Option<EcnCounts> {
+        self.ecn
+    }
+
+    // Smoothed RTT of the connection's path
+    pub fn rtt(&self) -> Duration {
+        self.conn_quic
+            .path_stats()
+            .next()
+            .map_or(Duration::ZERO, |path| path.rtt)
+    }
+
+    // Body bytes held for responses which haven't completed yet
+    pub fn buffered_bytes(&self) -> usize {
+        self.in_flight.values().map(|state| state.body.len()).sum()
+    }
+
+    // Fails every in-flight stream, once the connection is closed none of them will complete
+    pub fn abandon_in_flight(&mut self) -> Vec<StreamFailure> {
+        self.in_flight
+            .drain()
+            .map(|(id, state)| state.into_failure(id, StreamError::ConnectionClosed))
+            .collect()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.conn_quic.is_closed()
+    }
+
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        CloseReason::of(&self.conn_quic)
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.conn_quic.is_timed_out()
+    }
+
     pub fn has_in_flight(&self) -> bool {
         !self.in_flight.is_empty()
     }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+}
+
+// Offers the session ticket cached at `path`, if there's a usable one
+fn load_session(conn: &mut quiche::Connection, path: &std::path::Path) -> bool {
+    let Ok(session) = std::fs::read(path) else {
+        return false;
+    };
+
+    match conn.set_session(&session) {
+        Ok(()) => {
+            log::info!("resuming session from {}", path.display());
+            true
+        }
+        Err(e) => {
+            log::warn!("ignoring invalid session ticket {}: {e}", path.display());
+            false
+        }
+    }
+}
+
+// Tickets allow resuming the TLS session, so they are only readable by the user
+fn write_private(path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)?.write_all(data)
+}
+
+// Sends `packets`, each stored at a multiple of `mtu` in `out`, with as few sendmmsg calls as
+// the socket buffer allows. Returns how many were sent before it filled up
+#[cfg(target_os = "linux")]
+fn send_batch(
+    socket: &mio::net::UdpSocket,
+    out: &[u8],
+    mtu: usize,
+    packets: &[(usize, SocketAddr)],
+    gso: &mut bool,
+) -> std::io::Result<usize> {
+    use nix::sys::socket::{ControlMessage, MsgFlags, MultiHeaders, SockaddrStorage, sendmmsg};
+    use std::io::IoSlice;
+    use std::os::fd::AsRawFd;
+
+    if *gso && can_segment(packets) {
+        match send_segmented(socket, out, mtu, packets) {
+            Ok(()) => return Ok(packets.len()),
+            // the buffer is sent whole or not at all
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(0),
+            // the device can't offload checksums, nothing was sent
+            Err(e) if e.raw_os_error() == Some(nix::errno::Errno::EIO as i32) => {
+                log::info!("UDP segmentation offload unavailable, falling back to sendmmsg");
+                *gso = false;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let slices: Vec<[IoSlice; 1]> = packets
+        .iter()
+        .enumerate()
+        .map(|(i, (len, _))| [IoSlice::new(&out[i * mtu..i * mtu + len])])
+        .collect();
+    let addrs: Vec<Option<SockaddrStorage>> = packets
+        .iter()
+        .map(|(_, to)| Some(SockaddrStorage::from(*to)))
+        .collect();
+
+    // sendmmsg stops at the first datagram the socket buffer has no room for
+    let mut sent = 0;
+    while sent < packets.len() {
+        let mut headers = MultiHeaders::preallocate(packets.len() - sent, None);
+        match sendmmsg(
+            socket.as_raw_fd(),
+            &mut headers,
+            &slices[sent..],
+            &addrs[sent..],
+            &[] as &[ControlMessage],
+            MsgFlags::empty(),
+        ) {
+            Ok(results) => match results.count() {
+                0 => break,
+                count => sent += count,
+            },
+            Err(nix::errno::Errno::EAGAIN) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    log::trace!("sent {sent}/{} datagrams", packets.len());
+
+    Ok(sent)
+}
+
+// Sends `packets` as a single buffer which the kernel splits into datagrams
+#[cfg(target_os = "linux")]
+fn send_segmented(
+    socket: &mio::net::UdpSocket,
+    out: &[u8],
+    mtu: usize,
+    packets: &[(usize, SocketAddr)],
+) -> std::io::Result<()> {
+    use nix::sys::socket::{ControlMessage, MsgFlags, SockaddrStorage, sendmsg};
+    use std::io::IoSlice;
+    use std::os::fd::AsRawFd;
+
+    let slices: Vec<IoSlice> = packets
+        .iter()
+        .enumerate()
+        .map(|(i, (len, _))| IoSlice::new(&out[i * mtu..i * mtu + len]))
+        .collect();
+    let segment = packets[0].0 as u16;
+    let addr = SockaddrStorage::from(packets[0].1);
+
+    sendmsg(
+        socket.as_raw_fd(),
+        &slices,
+        &[ControlMessage::UdpGsoSegments(&segment)],
+        MsgFlags::empty(),
+        Some(&addr),
+    )?;
+    log::trace!("sent {} datagrams as one segmented buffer", packets.len());
+
+    Ok(())
+}
+
+// Segmentation needs one destination and equally sized datagrams, only the last may be shorter
+#[cfg(target_os = "linux")]
+fn can_segment(packets: &[(usize, SocketAddr)]) -> bool {
+    let Some(((size, to), rest)) = packets.split_first() else {
+        return false;
+    };
+
+    !rest.is_empty()
+        && rest.iter().all(|(_, addr)| addr == to)
+        && rest[..rest.len() - 1].iter().all(|(len, _)| len == size)
+        && rest[rest.len() - 1].0 <= *size
+}
+
+#[cfg(target_os = "linux")]
+fn supports_gso(socket: &mio::net::UdpSocket) -> bool {
+    nix::sys::socket::getsockopt(socket, nix::sys::socket::sockopt::UdpGsoSegment).is_ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn supports_gso(_socket: &mio::net::UdpSocket) -> bool {
+    false
+}
+
+// Marks outgoing packets ECT(0) and has the kernel pass the ECN bits of incoming ones
+#[cfg(target_os = "linux")]
+fn enable_ecn(socket: &mio::net::UdpSocket) -> std::io::Result<()> {
+    use nix::sys::socket::{setsockopt, sockopt};
+
+    if socket.local_addr()?.is_ipv4() {
+        setsockopt(socket, sockopt::Ipv4Tos, &ECT0)?;
+        setsockopt(socket, sockopt::IpRecvTos, &true)?;
+    } else {
+        setsockopt(socket, sockopt::Ipv6TClass, &ECT0)?;
+        setsockopt(socket, sockopt::Ipv6RecvTClass, &true)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enable_ecn(_socket: &mio::net::UdpSocket) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "not supported on this platform",
+    ))
+}
+
+// recv_from which also returns the ECN bits of the datagram, None when the kernel didn't
+// pass them
+#[cfg(target_os = "linux")]
+fn recv_with_ecn(
+    socket: &mio::net::UdpSocket,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, SocketAddr, Option<u8>)> {
+    use nix::sys::socket::{ControlMessageOwned, MsgFlags, SockaddrStorage, recvmsg};
+    use std::io::IoSliceMut;
+    use std::os::fd::AsRawFd;
+
+    let mut iov = [IoSliceMut::new(buf)];
+    let mut cmsg = nix::cmsg_space!(i32);
+    let msg = recvmsg::<SockaddrStorage>(
+        socket.as_raw_fd(),
+        &mut iov,
+        Some(cmsg.as_mut_slice()),
+        MsgFlags::empty(),
+    )?;
+
+    let from = msg
+        .address
+        .and_then(
+            |addr| match (addr.as_sockaddr_in(), addr.as_sockaddr_in6()) {
+                (Some(v4), _) => Some(SocketAddr::from(*v4)),
+                (_, Some(v6)) => Some(SocketAddr::from(*v6)),
+                _ => None,
+            },
+        )
+        .ok_or_else(|| std::io::Error::other("datagram without a source address"))?;
+    let ecn = msg.cmsgs()?.find_map(|cmsg| match cmsg {
+        ControlMessageOwned::Ipv4Tos(tos) => Some(tos & 0b11),
+        ControlMessageOwned::Ipv6TClass(class) => Some(class as u8 & 0b11),
+        _ => None,
+    });
+
+    Ok((msg.bytes, from, ecn))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn recv_with_ecn(
+    socket: &mio::net::UdpSocket,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, SocketAddr, Option<u8>)> {
+    socket.recv_from(buf).map(|(len, from)| (len, from, None))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_batch(
+    socket: &mio::net::UdpSocket,
+    out: &[u8],
+    mtu: usize,
+    packets: &[(usize, SocketAddr)],
+    _gso: &mut bool,
+) -> std::io::Result<usize> {
+    for (i, (len, to)) in packets.iter().enumerate() {
+        match socket.send_to(&out[i * mtu..i * mtu + len], *to) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(i),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(packets.len())
 }
 
 fn hex_dump(buf: &[u8]) -> String {
@@ -291,12 +1150,134 @@ fn hex_dump(buf: &[u8]) -> String {
     vec.join("")
 }
 
+// ECN codepoints of received packets, see --ecn
+#[derive(Clone, Copy, Default)]
+pub struct EcnCounts {
+    pub not_ect: u64,
+    pub ect0: u64,
+    pub ect1: u64,
+    pub ce: u64,      // congestion experienced, a router on the path marked the packet
+    pub unknown: u64, // the kernel didn't pass the bits
+}
+
+impl EcnCounts {
+    fn record(&mut self, ecn: Option<u8>) {
+        match ecn {
+            Some(0b00) => self.not_ect += 1,
+            Some(0b01) => self.ect1 += 1,
+            Some(0b10) => self.ect0 += 1,
+            Some(_) => self.ce += 1,
+            None => self.unknown += 1,
+        }
+    }
+}
+
+impl fmt::Display for EcnCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ECT(0) {}, ECT(1) {}, CE {}, not-ECT {}",
+            self.ect0, self.ect1, self.ce, self.not_ect
+        )?;
+        if self.unknown > 0 {
+            write!(f, ", unknown {}", self.unknown)?;
+        }
+        Ok(())
+    }
+}
+
+// Outcome of Client::probe_datagram
+#[derive(Default)]
+pub struct DatagramProbe {
+    pub max_len: Option<usize>, // largest datagram the peer accepts, None without datagram support
+    pub h3_datagram: bool,      // peer sent SETTINGS_H3_DATAGRAM
+    pub round_trip: Option<(Duration, usize)>, // time and length of the first datagram back
+}
+
+impl fmt::Display for DatagramProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(max_len) = self.max_len else {
+            return write!(f, "datagrams not supported");
+        };
+        write!(f, "datagrams up to {max_len} bytes")?;
+        if !self.h3_datagram {
+            write!(f, ", without SETTINGS_H3_DATAGRAM")?;
+        }
+        match self.round_trip {
+            Some((rtt, len)) => write!(f, ", {len} byte reply after {rtt:?}"),
+            None => write!(f, ", no reply"),
+        }
+    }
+}
+
+// Parameters negotiated during the handshake
+pub struct ConnectionInfo {
+    pub alpn: String,
+    pub version: u32,
+    pub offered_version: u32, // version of the first Initial, differs when the server negotiated
+    pub cc: config::CongestionControl,
+    pub peer_subject: Option<String>, // None when the server sent no parsable certificate
+    pub peer_issuer: Option<String>,
+    pub resumed: bool,                   // handshake used a cached session ticket
+    pub max_ack_delay: u64,              // ms, ours
+    pub peer_max_ack_delay: Option<u64>, // ms, None before the server's transport parameters arrived
+}
+
+impl ConnectionInfo {
+    // Longest ACK delays of both sides, e.g. "25ms (server 20ms)"
+    pub fn ack_delays(&self) -> String {
+        match self.peer_max_ack_delay {
+            Some(peer) => format!("{}ms (server {peer}ms)", self.max_ack_delay),
+            None => format!("{}ms", self.max_ack_delay),
+        }
+    }
+
+    // Suffix for messages about the version, empty without version negotiation
+    pub fn negotiation(&self) -> String {
+        if self.version != self.offered_version {
+            format!(" (negotiated, {:#x} was offered)", self.offered_version)
+        } else {
+            String::new()
+        }
+    }
+}
+
+impl fmt::Display for ConnectionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "ALPN:         {}", self.alpn)?;
+        writeln!(
+            f,
+            "QUIC version: {:#010x}{}",
+            self.version,
+            self.negotiation()
+        )?;
+        writeln!(f, "congestion:   {:?}", self.cc)?;
+        if let Some(subject) = &self.peer_subject {
+            writeln!(f, "cert subject: {subject}")?;
+        }
+        if let Some(issuer) = &self.peer_issuer {
+            writeln!(f, "cert issuer:  {issuer}")?;
+        }
+        writeln!(f, "resumed:      {}", self.resumed)?;
+        writeln!(f, "ACK delay:    {}", self.ack_delays())?;
+
+        Ok(())
+    }
+}
+
 // Struct which stores sent request, but which response haven't been received yet
 struct InFlight {
     path: String,
     status: Option<u16>,
     headers: HashMap<String, String>,
+    trailers: HashMap<String, String>,
     body: Vec<u8>,
+    file: Option<BodyFile>, // body written to disk instead, see QuicConfig::stream_bodies
+    sent_at: Instant,
+    error: Option<StreamError>,
+    received: usize, // body bytes received, including the ones beyond the size cap
+    headers_truncated: bool, // headers or trailers beyond the cap were dropped
+    unsent: Option<Vec<u8>>, // request body left to send, None once the stream is finished
 }
 
 impl InFlight {
@@ -305,18 +1286,428 @@ impl InFlight {
             path: path.to_string(),
             status: None,
             headers: HashMap::new(),
+            trailers: HashMap::new(),
             body: Vec::new(),
+            file: None,
+            sent_at: Instant::now(),
+            error: None,
+            received: 0,
+            headers_truncated: false,
+            unsent: None,
+        }
+    }
+
+    // Body bytes kept so far, in memory or on disk
+    fn kept(&self) -> usize {
+        self.file.as_ref().map_or(self.body.len(), |file| file.len)
+    }
+
+    // Appends to the body file, creating it with the first chunk
+    fn write_body_file(&mut self, dir: &Path, name: &str, chunk: &[u8]) -> std::io::Result<()> {
+        if self.file.is_none() {
+            self.file = Some(BodyFile::create(dir.join(name))?);
+        }
+        match self.file.as_mut() {
+            Some(file) => file.write(chunk),
+            None => Ok(()),
+        }
+    }
+
+    fn into_response(mut self, stream_id: u64) -> Result<http::Response, StreamFailure> {
+        let kept = self.kept();
+        let status = match (self.error.take(), self.status) {
+            (Some(error), _) => Err(error),
+            (None, Some(status)) => Ok(status),
+            (None, None) => Err(StreamError::MissingStatus),
+        };
+        let body_file = match (status.is_ok(), self.file.take()) {
+            (true, Some(file)) => match file.finish() {
+                Ok(path) => Some(path),
+                Err(e) => return Err(self.into_failure(stream_id, StreamError::BodyFile(e))),
+            },
+            (false, Some(file)) => {
+                file.discard();
+                None
+            }
+            (_, None) => None,
+        };
+
+        match status {
+            Ok(status) => {
+                let mut resp = http::Response::new(
+                    stream_id,
+                    &self.path,
+                    status,
+                    self.headers,
+                    self.body,
+                    self.sent_at.elapsed(),
+                );
+                resp.body_file = body_file;
+                resp.truncated = self.received > kept;
+                resp.received = self.received;
+                resp.trailers = self.trailers;
+                resp.headers_truncated = self.headers_truncated;
+                Ok(resp)
+            }
+            Err(error) => Err(self.into_failure(stream_id, error)),
+        }
+    }
+
+    fn into_failure(self, stream_id: u64, error: StreamError) -> StreamFailure {
+        if let Some(file) = self.file {
+            file.discard();
+        }
+
+        StreamFailure {
+            stream_id,
+            path: self.path,
+            error,
         }
     }
 }
 
+// Response body being written to disk
+struct BodyFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    len: usize, // bytes written
+}
+
+impl BodyFile {
+    fn create(path: PathBuf) -> std::io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(&path)?),
+            path,
+            len: 0,
+        })
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(chunk)?;
+        self.len += chunk.len();
+        Ok(())
+    }
+
+    // Flushes the file and returns its path, the file is removed when that fails
+    fn finish(mut self) -> std::io::Result<PathBuf> {
+        if let Err(e) = self.writer.flush() {
+            self.discard();
+            return Err(e);
+        }
+        Ok(self.path)
+    }
+
+    // Removes the file of a body which won't be part of a response
+    fn discard(self) {
+        drop(self.writer);
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::debug!("failed to remove body file {}: {e}", self.path.display());
+        }
+    }
+}
+
+// Reasons a handshake didn't complete, the first two usually mean there's no HTTP/3 server
+#[derive(thiserror::Error, Debug)]
+pub enum HandshakeError {
+    #[error("no QUIC response from the server, it may not support HTTP/3 or UDP may be blocked")]
+    TimedOut,
+    #[error("server doesn't offer HTTP/3 (h3 ALPN rejected)")]
+    AlpnRejected,
+    #[error("{0}")]
+    Closed(CloseReason),
+}
+
+impl HandshakeError {
+    // TLS alert no_application_protocol
+    const NO_APPLICATION_PROTOCOL: u8 = 120;
+
+    fn from_closed(conn: &quiche::Connection) -> Self {
+        let Some(reason) = CloseReason::of(conn) else {
+            return Self::TimedOut;
+        };
+
+        if reason.tls_alert() == Some(Self::NO_APPLICATION_PROTOCOL) {
+            return Self::AlpnRejected;
+        }
+
+        Self::Closed(reason)
+    }
+
+    pub fn is_missing_h3(&self) -> bool {
+        matches!(self, Self::TimedOut | Self::AlpnRejected)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("failed to bind {addr}")]
+pub struct BindError {
+    pub addr: SocketAddr,
+    #[source]
+    pub source: std::io::Error,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ClientError {
     #[error("in-flight queue is full")]
     InFlightFull,
     #[error("stream error")]
     WouldBlock,
+    #[error("connection is draining after GOAWAY")]
+    Draining,
 
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
+
+// Stream which completed without a usable response
+#[derive(Debug)]
+pub struct StreamFailure {
+    pub stream_id: u64,
+    pub path: String,
+    pub error: StreamError,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum StreamError {
+    #[error("invalid :status {0:?}")]
+    InvalidStatus(String),
+    #[error("missing :status")]
+    MissingStatus,
+    #[error("stream reset by server with error code {0:#x}")]
+    Reset(u64),
+    #[error("stream dropped by server GOAWAY")]
+    GoAway,
+    #[error("request timed out")]
+    TimedOut,
+    #[error("connection closed before the response completed")]
+    ConnectionClosed,
+    #[error("failed to write response body to disk: {0}")]
+    BodyFile(std::io::Error),
+}
+
+impl StreamError {
+    // Failures which may succeed when the request is sent again
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            StreamError::Reset(_)
+                | StreamError::GoAway
+                | StreamError::TimedOut
+                | StreamError::ConnectionClosed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cert.crt");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cert.key");
+
+    // Client connected to an in-memory server, packets are moved by `pump` instead of sockets
+    struct Pair {
+        client: Client,
+        server: quiche::Connection,
+        server_h3: quiche::h3::Connection,
+    }
+
+    impl Pair {
+        fn new() -> Self {
+            let url = url::Url::parse("https://127.0.0.1:4433/").unwrap();
+            let args = config::QuicConfig::new(&url, true).unwrap();
+
+            let mut client_config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+            client_config.verify_peer(false);
+            client_config
+                .set_application_protos(quiche::h3::APPLICATION_PROTOCOL)
+                .unwrap();
+            client_config.set_initial_max_data(args.max_data);
+            client_config.set_initial_max_stream_data_bidi_local(1_000_000);
+            client_config.set_initial_max_stream_data_uni(1_000_000);
+            client_config.set_initial_max_streams_uni(100);
+
+            let mut server_config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+            server_config.load_cert_chain_from_pem_file(CERT).unwrap();
+            server_config.load_priv_key_from_pem_file(KEY).unwrap();
+            server_config
+                .set_application_protos(quiche::h3::APPLICATION_PROTOCOL)
+                .unwrap();
+            server_config.set_initial_max_data(1_000_000);
+            server_config.set_initial_max_stream_data_bidi_remote(1_000_000);
+            server_config.set_initial_max_stream_data_uni(1_000_000);
+            server_config.set_initial_max_streams_bidi(100);
+            server_config.set_initial_max_streams_uni(100);
+
+            let socket = mio::net::UdpSocket::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            let local = socket.local_addr().unwrap();
+            let peer = SocketAddr::V4(args.remote_addr);
+
+            let client_scid = quiche::ConnectionId::from_ref(&[1; quiche::MAX_CONN_ID_LEN]);
+            let server_scid = quiche::ConnectionId::from_ref(&[2; quiche::MAX_CONN_ID_LEN]);
+            let conn_quic = quiche::connect(
+                Some(&args.server_name),
+                &client_scid,
+                local,
+                peer,
+                &mut client_config,
+            )
+            .unwrap();
+            let mut server =
+                quiche::accept(&server_scid, None, peer, local, &mut server_config).unwrap();
+
+            let mut client = Client {
+                buf: vec![0; args.mtu],
+                out: vec![0; args.mtu * SEND_BATCH],
+                unsent: Vec::new(),
+                gso: false,
+                body_buf: vec![0; BODY_CHUNK],
+                args,
+                draining: false,
+                poll: mio::Poll::new().unwrap(),
+                events: mio::Events::with_capacity(16),
+                socket,
+                old_socket: None,
+                conn_quic,
+                conn_h3: None,
+                in_flight: HashMap::new(),
+                tunnel: None,
+                session_saved: false,
+                ecn: None,
+            };
+            pump(&mut client.conn_quic, &mut server);
+            assert!(client.conn_quic.is_established());
+
+            client.ensure_h3().unwrap();
+            let server_h3 = quiche::h3::Connection::with_transport(
+                &mut server,
+                &quiche::h3::Config::new().unwrap(),
+            )
+            .unwrap();
+            pump(&mut client.conn_quic, &mut server);
+
+            Pair {
+                client,
+                server,
+                server_h3,
+            }
+        }
+
+        fn pump(&mut self) {
+            pump(&mut self.client.conn_quic, &mut self.server);
+        }
+
+        // Sends a GET for `path` and returns the stream it arrived on at the server
+        fn request(&mut self, path: &str) -> u64 {
+            let req = http::Request::new("https", "127.0.0.1", "GET", path, Vec::new());
+            let id = self.client.send_request(&req).unwrap();
+            self.pump();
+
+            loop {
+                match self.server_h3.poll(&mut self.server) {
+                    Ok((stream_id, quiche::h3::Event::Headers { .. })) => {
+                        assert_eq!(stream_id, id);
+                        return stream_id;
+                    }
+                    Ok(_) => {}
+                    Err(e) => panic!("request never reached the server: {e}"),
+                }
+            }
+        }
+
+        fn respond(&mut self, id: u64, headers: &[(&str, &str)], body: &[u8], fin: bool) {
+            let headers: Vec<quiche::h3::Header> = headers
+                .iter()
+                .map(|(name, value)| quiche::h3::Header::new(name.as_bytes(), value.as_bytes()))
+                .collect();
+            self.server_h3
+                .send_response(&mut self.server, id, &headers, false)
+                .unwrap();
+            self.server_h3
+                .send_body(&mut self.server, id, body, fin)
+                .unwrap();
+            self.pump();
+        }
+    }
+
+    // Delivers packets both ways until neither side has anything left to send
+    fn pump(client: &mut quiche::Connection, server: &mut quiche::Connection) {
+        let mut buf = [0; 65535];
+        loop {
+            let mut moved = false;
+            while let Ok((len, info)) = client.send(&mut buf) {
+                let info = quiche::RecvInfo {
+                    from: info.from,
+                    to: info.to,
+                };
+                server.recv(&mut buf[..len], info).unwrap();
+                moved = true;
+            }
+            while let Ok((len, info)) = server.send(&mut buf) {
+                let info = quiche::RecvInfo {
+                    from: info.from,
+                    to: info.to,
+                };
+                client.recv(&mut buf[..len], info).unwrap();
+                moved = true;
+            }
+            if !moved {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn reset_mid_response_is_a_failure() {
+        let mut pair = Pair::new();
+        let id = pair.request("/partial");
+
+        pair.respond(id, &[(":status", "200")], b"first half of the", false);
+        assert!(pair.client.poll_responses().unwrap().is_empty());
+
+        pair.server
+            .stream_shutdown(id, quiche::Shutdown::Write, 0x10c)
+            .unwrap();
+        pair.pump();
+
+        let mut completed = pair.client.poll_responses().unwrap();
+        assert_eq!(completed.len(), 1);
+        match completed.pop().unwrap() {
+            Err(failure) => {
+                assert_eq!(failure.stream_id, id);
+                assert_eq!(failure.path, "/partial");
+                assert!(matches!(failure.error, StreamError::Reset(0x10c)));
+            }
+            Ok(resp) => panic!("reset stream reported as complete {} response", resp.status),
+        }
+        assert!(!pair.client.has_in_flight());
+    }
+
+    #[test]
+    fn grpc_status_lands_in_trailers() {
+        let mut pair = Pair::new();
+        let id = pair.request("/helloworld.Greeter/SayHello");
+
+        let headers = [(":status", "200"), ("content-type", "application/grpc")];
+        pair.respond(id, &headers, b"\0\0\0\0\x02\x08\x01", false);
+        let trailers = [
+            quiche::h3::Header::new(b"grpc-status", b"7"),
+            quiche::h3::Header::new(b"grpc-message", b"permission denied"),
+        ];
+        pair.server_h3
+            .send_additional_headers(&mut pair.server, id, &trailers, true, true)
+            .unwrap();
+        pair.pump();
+
+        let mut completed = pair.client.poll_responses().unwrap();
+        assert_eq!(completed.len(), 1);
+        let resp = completed.pop().unwrap().unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body, b"\0\0\0\0\x02\x08\x01");
+        assert_eq!(resp.headers["content-type"], "application/grpc");
+        assert_eq!(resp.trailers["grpc-status"], "7");
+        assert_eq!(resp.trailers["grpc-message"], "permission denied");
+        assert!(!resp.headers.contains_key("grpc-status"));
+        assert!(!resp.headers.contains_key("grpc-message"));
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+
+// Response header bytes read before giving up on the end of the header block
+const MAX_HEAD: usize = 64 * 1024;
+
+// Asks the TLS (HTTP/1.1) server at addr which UDP port it serves HTTP/3 on, from the h3 entry
+// of its Alt-Svc header (RFC 7838). None when it doesn't advertise one for this host
+pub fn discover_h3_port(
+    server_name: &str,
+    authority: &str,
+    addr: SocketAddr,
+    verify_peer: bool,
+) -> anyhow::Result<Option<u16>> {
+    let mut tls = super::tls::connect(server_name, addr, verify_peer)?;
+
+    write!(
+        tls,
+        "HEAD / HTTP/1.1\r\nhost: {authority}\r\nconnection: close\r\n\r\n"
+    )?;
+    tls.flush()?;
+
+    let mut head = Vec::new();
+    let mut buf = [0; 4096];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_HEAD {
+        match tls.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => head.extend_from_slice(&buf[..n]),
+            // servers often drop the connection without close_notify after the response
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    Ok(head
+        .split("\r\n")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("alt-svc"))
+        .find_map(|(_, value)| h3_port(value, server_name)))
+}
+
+// Port of the first h3 alternative in an Alt-Svc value, e.g. `h3=":8443"; ma=86400`.
+// Alternatives on another host are skipped, the connection goes to the resolved address
+fn h3_port(value: &str, host: &str) -> Option<u16> {
+    value.split(',').find_map(|alternative| {
+        let (protocol, authority) = alternative.split(';').next()?.split_once('=')?;
+        if protocol.trim() != "h3" {
+            return None;
+        }
+
+        let (alt_host, port) = authority.trim().trim_matches('"').rsplit_once(':')?;
+        if !alt_host.is_empty() && !alt_host.eq_ignore_ascii_case(host) {
+            log::debug!("skipping Alt-Svc alternative on another host: {authority}");
+            return None;
+        }
+        port.parse().ok()
+    })
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+
+// Why a connection was closed, from the CONNECTION_CLOSE frame either side sent
+#[derive(Debug, Clone)]
+pub struct CloseReason {
+    pub by_peer: bool, // the server sent the frame, otherwise quiche did on our side
+    pub is_app: bool,  // HTTP/3 error code rather than a transport one
+    pub code: u64,
+    pub reason: String,
+}
+
+impl CloseReason {
+    // Reason of a closed connection, None when it just timed out
+    pub fn of(conn: &quiche::Connection) -> Option<Self> {
+        let (by_peer, err) = match (conn.peer_error(), conn.local_error()) {
+            (Some(err), _) => (true, err),
+            (None, Some(err)) => (false, err),
+            (None, None) => return None,
+        };
+
+        Some(Self {
+            by_peer,
+            is_app: err.is_app,
+            code: err.error_code,
+            reason: String::from_utf8_lossy(&err.reason).into_owned(),
+        })
+    }
+
+    // TLS alert carried by a CRYPTO_ERROR, e.g. 42 for a bad certificate
+    pub fn tls_alert(&self) -> Option<u8> {
+        (!self.is_app && (0x100..0x200).contains(&self.code)).then(|| (self.code - 0x100) as u8)
+    }
+
+    fn name(&self) -> &'static str {
+        if self.is_app {
+            return h3_error_name(self.code);
+        }
+        if self.tls_alert().is_some() {
+            return "CRYPTO_ERROR";
+        }
+        transport_error_name(self.code)
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = if self.by_peer { "server" } else { "client" };
+        write!(f, "{side} closed: {}({:#x})", self.name(), self.code)?;
+        if let Some(alert) = self.tls_alert().and_then(tls_alert_name) {
+            write!(f, " {alert}")?;
+        }
+        if !self.reason.is_empty() {
+            write!(f, ": {}", self.reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+// RFC 9000, section 20.1
+fn transport_error_name(code: u64) -> &'static str {
+    match code {
+        0x0 => "NO_ERROR",
+        0x1 => "INTERNAL_ERROR",
+        0x2 => "CONNECTION_REFUSED",
+        0x3 => "FLOW_CONTROL_ERROR",
+        0x4 => "STREAM_LIMIT_ERROR",
+        0x5 => "STREAM_STATE_ERROR",
+        0x6 => "FINAL_SIZE_ERROR",
+        0x7 => "FRAME_ENCODING_ERROR",
+        0x8 => "TRANSPORT_PARAMETER_ERROR",
+        0x9 => "CONNECTION_ID_LIMIT_ERROR",
+        0xa => "PROTOCOL_VIOLATION",
+        0xb => "INVALID_TOKEN",
+        0xc => "APPLICATION_ERROR",
+        0xd => "CRYPTO_BUFFER_EXCEEDED",
+        0xe => "KEY_UPDATE_ERROR",
+        0xf => "AEAD_LIMIT_REACHED",
+        0x10 => "NO_VIABLE_PATH",
+        _ => "UNKNOWN_ERROR",
+    }
+}
+
+// RFC 9114, section 8.1 and RFC 9204, section 6
+fn h3_error_name(code: u64) -> &'static str {
+    match code {
+        0x100 => "H3_NO_ERROR",
+        0x101 => "H3_GENERAL_PROTOCOL_ERROR",
+        0x102 => "H3_INTERNAL_ERROR",
+        0x103 => "H3_STREAM_CREATION_ERROR",
+        0x104 => "H3_CLOSED_CRITICAL_STREAM",
+        0x105 => "H3_FRAME_UNEXPECTED",
+        0x106 => "H3_FRAME_ERROR",
+        0x107 => "H3_EXCESSIVE_LOAD",
+        0x108 => "H3_ID_ERROR",
+        0x109 => "H3_SETTINGS_ERROR",
+        0x10a => "H3_MISSING_SETTINGS",
+        0x10b => "H3_REQUEST_REJECTED",
+        0x10c => "H3_REQUEST_CANCELLED",
+        0x10d => "H3_REQUEST_INCOMPLETE",
+        0x10e => "H3_MESSAGE_ERROR",
+        0x10f => "H3_CONNECT_ERROR",
+        0x110 => "H3_VERSION_FALLBACK",
+        0x200 => "QPACK_DECOMPRESSION_FAILED",
+        0x201 => "QPACK_ENCODER_STREAM_ERROR",
+        0x202 => "QPACK_DECODER_STREAM_ERROR",
+        _ => "UNKNOWN_APPLICATION_ERROR",
+    }
+}
+
+// RFC 8446, section 6, the alerts a QUIC handshake may end with
+fn tls_alert_name(alert: u8) -> Option<&'static str> {
+    Some(match alert {
+        10 => "unexpected message",
+        20 => "bad record mac",
+        40 => "handshake failure",
+        42 => "bad certificate",
+        43 => "unsupported certificate",
+        44 => "certificate revoked",
+        45 => "certificate expired",
+        46 => "certificate unknown",
+        47 => "illegal parameter",
+        48 => "unknown CA",
+        50 => "decode error",
+        51 => "decrypt error",
+        70 => "protocol version",
+        71 => "insufficient security",
+        80 => "internal error",
+        86 => "inappropriate fallback",
+        109 => "missing extension",
+        110 => "unsupported extension",
+        112 => "unrecognized name",
+        116 => "certificate required",
+        120 => "no application protocol",
+        _ => return None,
+    })
+}
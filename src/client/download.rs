@@ -0,0 +1,165 @@
+use super::tls;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::ToSocketAddrs;
+
+// Redirects followed before a download is given up
+const MAX_REDIRECTS: usize = 5;
+
+// Response header bytes read before giving up on the end of the header block
+const MAX_HEAD: usize = 64 * 1024;
+
+trait Connection: Read + Write {}
+impl<T: Read + Write> Connection for T {}
+
+// Response whose header block was read, the body is next on the connection
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    conn: BufReader<Box<dyn Connection>>,
+}
+
+// Fetches an http(s) URL over HTTP/1.1 and writes the body to `out`, following redirects.
+// Returns the number of body bytes
+pub fn download(url: &url::Url, verify_peer: bool, out: &mut impl Write) -> anyhow::Result<u64> {
+    let mut url = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        let mut resp = get(&url, verify_peer)?;
+        match resp.status {
+            200 => return resp.copy_body(out),
+            301 | 302 | 303 | 307 | 308 => {
+                let location = resp.header("location").ok_or_else(|| {
+                    anyhow::anyhow!("redirect {} without a location", resp.status)
+                })?;
+                url = url.join(location)?;
+                log::info!("following redirect to {url}");
+            }
+            status => anyhow::bail!("server answered {status}"),
+        }
+    }
+    anyhow::bail!("more than {MAX_REDIRECTS} redirects")
+}
+
+fn get(url: &url::Url, verify_peer: bool) -> anyhow::Result<Response> {
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        anyhow::bail!("URL missing host or port");
+    };
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no address found for {host}"))?;
+
+    let mut conn: Box<dyn Connection> = match url.scheme() {
+        "https" => Box::new(tls::connect(host, addr, verify_peer)?),
+        "http" => Box::new(tls::connect_tcp(addr)?),
+        scheme => anyhow::bail!("unsupported scheme {scheme}"),
+    };
+    write!(
+        conn,
+        "GET {} HTTP/1.1\r\nhost: {}\r\nuser-agent: {}\r\naccept-encoding: identity\r\nconnection: close\r\n\r\n",
+        &url[url::Position::BeforePath..url::Position::AfterQuery],
+        &url[url::Position::BeforeHost..url::Position::AfterPort],
+        crate::config::DEFAULT_USER_AGENT
+    )?;
+    conn.flush()?;
+
+    let mut conn = BufReader::new(conn);
+    let mut line = String::new();
+    conn.read_line(&mut line)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("invalid status line: {}", line.trim_end()))?;
+
+    let mut headers = Vec::new();
+    let mut head_len = line.len();
+    loop {
+        line.clear();
+        let n = conn.read_line(&mut line)?;
+        head_len += n;
+        if n == 0 || head_len > MAX_HEAD {
+            anyhow::bail!("incomplete response header block");
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    Ok(Response {
+        status,
+        headers,
+        conn,
+    })
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn copy_body(&mut self, out: &mut impl Write) -> anyhow::Result<u64> {
+        if self
+            .header("transfer-encoding")
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"))
+        {
+            return self.copy_chunked(out);
+        }
+
+        if let Some(len) = self.header("content-length") {
+            let len: u64 = len.parse()?;
+            let copied = std::io::copy(&mut (&mut self.conn).take(len), out)?;
+            if copied < len {
+                anyhow::bail!("connection closed after {copied} of {len} bytes");
+            }
+            return Ok(copied);
+        }
+
+        // without either the body ends with the connection
+        let mut copied = 0;
+        let mut buf = [0; 16 * 1024];
+        loop {
+            match self.conn.read(&mut buf) {
+                Ok(0) => return Ok(copied),
+                Ok(n) => {
+                    out.write_all(&buf[..n])?;
+                    copied += n as u64;
+                }
+                // servers often drop the connection without close_notify after the response
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(copied),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn copy_chunked(&mut self, out: &mut impl Write) -> anyhow::Result<u64> {
+        let mut copied = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            self.conn.read_line(&mut line)?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = u64::from_str_radix(size, 16)
+                .map_err(|_| anyhow::anyhow!("invalid chunk size: {}", line.trim_end()))?;
+
+            if size == 0 {
+                // trailers end with an empty line, the connection closes after them anyway
+                return Ok(copied);
+            }
+
+            let n = std::io::copy(&mut (&mut self.conn).take(size), out)?;
+            if n < size {
+                anyhow::bail!("connection closed in the middle of a chunk");
+            }
+            copied += n;
+
+            line.clear();
+            self.conn.read_line(&mut line)?; // CRLF after the chunk
+        }
+    }
+}
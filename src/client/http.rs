@@ -1,4 +1,83 @@
+use percent_encoding::{AsciiSet, CONTROLS, NON_ALPHANUMERIC, utf8_percent_encode};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+// Characters which can't appear raw in a path segment
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+// Everything but unreserved characters, so a word stays a single query value
+const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+static TITLE: LazyLock<regex::bytes::Regex> =
+    LazyLock::new(|| regex::bytes::Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+
+// Placeholder in the query string replaced by each word with --fuzz-query
+pub const FUZZ_KEYWORD: &str = "FUZZ";
+
+// How words are encoded when put into the path
+#[derive(Clone, Copy, Debug, Default)]
+pub enum PathEncoding {
+    #[default]
+    Raw,
+    Percent,
+    Double, // percent-encoded twice, e.g. " " -> "%2520"
+}
+
+impl PathEncoding {
+    // Encodes each segment of `word`, keeping the slashes between them
+    pub fn encode(self, word: &str) -> String {
+        let times = match self {
+            PathEncoding::Raw => return word.to_string(),
+            PathEncoding::Percent => 1,
+            PathEncoding::Double => 2,
+        };
+
+        let segments: Vec<String> = word
+            .split('/')
+            .map(|segment| {
+                let mut segment = segment.to_string();
+                for _ in 0..times {
+                    segment = utf8_percent_encode(&segment, SEGMENT).to_string();
+                }
+                segment
+            })
+            .collect();
+
+        segments.join("/")
+    }
+
+    // Query values are always encoded once, Double encodes them again
+    pub fn encode_query(self, word: &str) -> String {
+        let value = utf8_percent_encode(word, QUERY_VALUE).to_string();
+
+        match self {
+            PathEncoding::Double => utf8_percent_encode(&value, QUERY_VALUE).to_string(),
+            _ => value,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Request {
@@ -6,7 +85,95 @@ pub struct Request {
     pub scheme: String,
     pub method: String,
     pub host: String,
-    pub headers: HashMap<String, String>,
+    pub headers: Vec<(String, String)>, // sent in this order after the pseudo-headers
+    pub encoding: PathEncoding,         // applied to words by with_path
+    pub query: Option<String>,          // query of the target URL, without the '?'
+    pub fuzz_query: bool, // words replace FUZZ_KEYWORD in the query instead of the path
+    pub template: bool,   // words replace FUZZ_KEYWORD in the path and header values
+    pub body: Option<Vec<u8>>, // None sends the request without a body
+    pub form: Option<Form>, // body rebuilt for every word by with_path
+}
+
+// multipart/form-data body (RFC 7578). Words replace FUZZ_KEYWORD in text field values
+#[derive(Clone, Debug)]
+pub struct Form {
+    boundary: String,
+    fields: Vec<FormField>,
+}
+
+#[derive(Clone, Debug)]
+pub enum FormField {
+    Text {
+        name: String,
+        value: String,
+    },
+    File {
+        name: String,
+        filename: String,
+        data: Arc<Vec<u8>>, // shared by the requests of all words
+    },
+}
+
+impl Form {
+    // `nonce` makes the boundary unlikely to appear in the fields
+    pub fn new(fields: Vec<FormField>, nonce: u64) -> Self {
+        let boundary = format!("fuzzh3-{nonce:016x}");
+        Self { boundary, fields }
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    pub fn encode(&self, word: &str) -> Vec<u8> {
+        // quotes would end the quoted name early
+        let quoted = |s: &str| s.replace('"', "%22");
+
+        let mut body = Vec::new();
+        for field in &self.fields {
+            body.extend_from_slice(format!("--{}\r\n", self.boundary).as_bytes());
+            match field {
+                FormField::Text { name, value } => {
+                    body.extend_from_slice(
+                        format!(
+                            "Content-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                            quoted(name)
+                        )
+                        .as_bytes(),
+                    );
+                    body.extend_from_slice(value.replace(FUZZ_KEYWORD, word).as_bytes());
+                }
+                FormField::File {
+                    name,
+                    filename,
+                    data,
+                } => {
+                    body.extend_from_slice(
+                        format!(
+                            "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                             Content-Type: application/octet-stream\r\n\r\n",
+                            quoted(name),
+                            quoted(filename)
+                        )
+                        .as_bytes(),
+                    );
+                    body.extend_from_slice(data);
+                }
+            }
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+
+        body
+    }
+}
+
+// Request read from a --request-file, before the URL's host and the options are applied
+#[derive(Clone, Debug)]
+pub struct RequestTemplate {
+    pub method: String,
+    pub path: String, // with the query
+    pub headers: Vec<(String, String)>,
 }
 
 impl Request {
@@ -15,15 +182,21 @@ impl Request {
         host: &str,
         method: &str,
         path: &str,
-        headers: HashMap<String, String>,
-    ) -> anyhow::Result<Self> {
-        Ok(Self {
+        headers: Vec<(String, String)>,
+    ) -> Self {
+        Self {
             scheme: scheme.to_string(),
             method: method.to_string(),
             host: host.to_string(),
             path: path.to_string(),
             headers,
-        })
+            encoding: PathEncoding::default(),
+            query: None,
+            fuzz_query: false,
+            template: false,
+            body: None,
+            form: None,
+        }
     }
 
     pub fn to_quiche(&self) -> Vec<quiche::h3::Header> {
@@ -34,38 +207,231 @@ impl Request {
             quiche::h3::Header::new(b":path", self.path.as_bytes()),
         ];
 
+        // names keep the user's casing in `headers`, HTTP/3 requires them lowercase on the wire
         for (k, v) in &self.headers {
-            headers.push(quiche::h3::Header::new(k.as_bytes(), v.as_bytes()));
+            let name = k.to_ascii_lowercase();
+            headers.push(quiche::h3::Header::new(name.as_bytes(), v.as_bytes()));
+        }
+        if let Some(body) = &self.body
+            && !self
+                .headers
+                .iter()
+                .any(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+        {
+            let len = body.len().to_string();
+            headers.push(quiche::h3::Header::new(b"content-length", len.as_bytes()));
         }
 
         headers
     }
 
+    // Sets a header, replacing one with the same name regardless of case in its place
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        let header = (name.to_string(), value.to_string());
+        match self
+            .headers
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(name))
+        {
+            Some(i) => {
+                self.headers[i] = header;
+                // drop later duplicates, e.g. from repeated -H flags
+                let rest = self.headers.split_off(i + 1);
+                self.headers.extend(
+                    rest.into_iter()
+                        .filter(|(k, _)| !k.eq_ignore_ascii_case(name)),
+                );
+            }
+            None => self.headers.push(header),
+        }
+        self
+    }
+
     pub fn with_path(&self, path: &str) -> Self {
         let mut r = self.clone();
-        r.path = format!("/{}", path);
+        if self.template {
+            r.path = match self.path.split_once('?') {
+                Some((base, query)) => format!(
+                    "{}?{}",
+                    base.replace(FUZZ_KEYWORD, &self.encoding.encode(path)),
+                    query.replace(FUZZ_KEYWORD, &self.encoding.encode_query(path))
+                ),
+                None => self.path.replace(FUZZ_KEYWORD, &self.encoding.encode(path)),
+            };
+            for (_, value) in &mut r.headers {
+                *value = value.replace(FUZZ_KEYWORD, path);
+            }
+            if let Some(form) = &self.form {
+                r.body = Some(form.encode(path));
+            }
+            return r;
+        }
+
+        r.path = match (&self.query, self.fuzz_query) {
+            (Some(query), true) => {
+                let query = query.replace(FUZZ_KEYWORD, &self.encoding.encode_query(path));
+                format!("{}?{query}", self.path)
+            }
+            (Some(query), false) => format!("/{}?{query}", self.encoding.encode(path)),
+            (None, _) => format!("/{}", self.encoding.encode(path)),
+        };
+        if let Some(form) = &self.form {
+            r.body = Some(form.encode(path));
+        }
         r
     }
 }
 
 pub struct Response {
+    pub stream_id: u64,
     pub path: String,
     pub status: u16,
     pub headers: HashMap<String, String>,
+    pub trailers: HashMap<String, String>, // headers sent after the body
     pub body: Vec<u8>,
+    pub body_file: Option<PathBuf>, // body was streamed here instead of kept in `body`
+    pub time: Duration, // time between sending the request and receiving the whole response
+    pub received: usize, // body bytes received, larger than body when truncated
+    pub truncated: bool,
+    pub headers_truncated: bool, // some headers or trailers were dropped, see --max-headers
+    pub redirects: Vec<String>,  // paths redirected from to reach this response, oldest first
 }
 
 impl Response {
-    pub fn new(path: &str, status: u16, headers: HashMap<String, String>, body: Vec<u8>) -> Self {
+    // Redirects followed to reach this response
+    pub fn redirect_count(&self) -> usize {
+        self.redirects.len()
+    }
+
+    pub fn new(
+        stream_id: u64,
+        path: &str,
+        status: u16,
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+        time: Duration,
+    ) -> Self {
         Self {
+            stream_id,
             path: path.to_string(),
             status,
             headers,
+            trailers: HashMap::new(),
+            received: body.len(),
             body,
+            body_file: None,
+            time,
+            truncated: false,
+            headers_truncated: false,
+            redirects: Vec::new(),
+        }
+    }
+
+    // Body kept of the response, read back from disk when it was streamed to a file
+    pub fn body_bytes(&self) -> Cow<'_, [u8]> {
+        let Some(path) = &self.body_file else {
+            return Cow::Borrowed(&self.body);
+        };
+
+        match std::fs::read(path) {
+            Ok(body) => Cow::Owned(body),
+            Err(e) => {
+                log::warn!("failed to read body file {}: {e}", path.display());
+                Cow::Borrowed(&[])
+            }
+        }
+    }
+
+    // Deletes the file of a streamed body, for responses which aren't reported
+    pub fn discard_body_file(&self) {
+        if let Some(path) = &self.body_file
+            && let Err(e) = std::fs::remove_file(path)
+        {
+            log::debug!("failed to remove body file {}: {e}", path.display());
         }
     }
 
     pub fn body_to_string(&self) -> anyhow::Result<String> {
-        Ok(String::from_utf8_lossy(&self.body).into_owned())
+        Ok(String::from_utf8_lossy(&self.body_bytes()).into_owned())
+    }
+
+    pub fn size(&self) -> usize {
+        self.received
+    }
+
+    // Word and line counts are unknown when only part of the body was kept
+    pub fn words(&self) -> Option<usize> {
+        (!self.truncated).then(|| {
+            String::from_utf8_lossy(&self.body_bytes())
+                .split_whitespace()
+                .count()
+        })
+    }
+
+    pub fn lines(&self) -> Option<usize> {
+        (!self.truncated).then(|| String::from_utf8_lossy(&self.body_bytes()).lines().count())
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get("content-type").map(String::as_str)
+    }
+
+    // Text of the <title> element of HTML responses, None for other content or an empty title
+    pub fn title(&self) -> Option<String> {
+        if !self.content_type()?.contains("html") {
+            return None;
+        }
+
+        let body = self.body_bytes();
+        let title = TITLE.captures(&body)?.get(1)?;
+        let title = String::from_utf8_lossy(title.as_bytes());
+        let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        (!title.is_empty()).then_some(title)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORD: &str = "a b#c?d/é";
+
+    #[test]
+    fn raw_path_is_unchanged() {
+        assert_eq!(PathEncoding::Raw.encode(WORD), WORD);
+        assert_eq!(PathEncoding::Raw.encode("100%"), "100%");
+    }
+
+    #[test]
+    fn percent_path_keeps_slashes() {
+        assert_eq!(PathEncoding::Percent.encode(WORD), "a%20b%23c%3Fd/%C3%A9");
+        assert_eq!(PathEncoding::Percent.encode("100%"), "100%25");
+    }
+
+    #[test]
+    fn double_path_escapes_percent() {
+        assert_eq!(
+            PathEncoding::Double.encode(WORD),
+            "a%2520b%2523c%253Fd/%25C3%25A9"
+        );
+        assert_eq!(PathEncoding::Double.encode("100%"), "100%2525");
+    }
+
+    #[test]
+    fn query_is_always_encoded() {
+        let once = "a%20b%23c%3Fd%2F%C3%A9";
+        assert_eq!(PathEncoding::Raw.encode_query(WORD), once);
+        assert_eq!(PathEncoding::Percent.encode_query(WORD), once);
+        assert_eq!(PathEncoding::Percent.encode_query("100%"), "100%25");
+    }
+
+    #[test]
+    fn double_query_escapes_percent() {
+        assert_eq!(
+            PathEncoding::Double.encode_query(WORD),
+            "a%2520b%2523c%253Fd%252F%25C3%25A9"
+        );
+        assert_eq!(PathEncoding::Double.encode_query("100%"), "100%2525");
     }
 }
@@ -0,0 +1,345 @@
+use super::HandshakeError;
+use crate::config;
+use quiche::h3::NameValue;
+use rand::RngCore;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+// How long opening the tunnel may take, from the first packet to the proxy's CONNECT response
+const SETUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Datagrams queued in each direction of the proxy connection
+const DGRAM_QUEUE_LEN: usize = 1000;
+
+// HTTP datagram context id of UDP payloads (RFC 9298)
+const UDP_PAYLOAD_CONTEXT: u64 = 0;
+
+#[derive(thiserror::Error, Debug)]
+pub enum TunnelError {
+    #[error("proxy handshake failed")]
+    Handshake(#[source] HandshakeError),
+    #[error("proxy doesn't support CONNECT-UDP (no extended CONNECT or HTTP datagrams)")]
+    Unsupported,
+    #[error("proxy refused CONNECT-UDP with status {0}")]
+    Refused(String),
+    #[error("proxy didn't open the tunnel in time")]
+    TimedOut,
+    #[error("proxy closed the tunnel")]
+    Closed,
+}
+
+// MASQUE CONNECT-UDP tunnel (RFC 9298): an HTTP/3 connection to a proxy which forwards the
+// packets of the target connection as HTTP datagrams
+pub struct Tunnel {
+    conn: quiche::Connection,
+    h3: Option<quiche::h3::Connection>,
+    stream_id: Option<u64>, // of the CONNECT request, the tunnel lives as long as it does
+    open: bool,             // proxy accepted the CONNECT request
+    closed: bool,
+    buf: Vec<u8>,   // receive buffer for packets from the proxy
+    out: Vec<u8>,   // send buffer for packets to the proxy
+    dgram: Vec<u8>, // scratch for building and reading datagrams
+}
+
+impl Tunnel {
+    // Connects to the proxy and asks it to open a UDP tunnel towards the target
+    pub fn open(
+        proxy: &config::Proxy,
+        args: &config::QuicConfig,
+        socket: &mio::net::UdpSocket,
+        poll: &mut mio::Poll,
+        events: &mut mio::Events,
+    ) -> anyhow::Result<Self> {
+        let mut config_quic = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+
+        config_quic.verify_peer(args.verify_peer);
+        config_quic.set_application_protos(quiche::h3::APPLICATION_PROTOCOL)?;
+        config_quic.set_max_recv_udp_payload_size(args.mtu);
+        config_quic.set_max_send_udp_payload_size(args.mtu);
+        config_quic.set_initial_max_data(args.max_data);
+        config_quic.set_initial_max_stream_data_bidi_local(1_000_000);
+        config_quic.set_initial_max_stream_data_bidi_remote(1_000_000);
+        config_quic.set_initial_max_stream_data_uni(1_000_000);
+        config_quic.set_initial_max_streams_bidi(100);
+        config_quic.set_initial_max_streams_uni(100);
+        config_quic.set_disable_active_migration(true);
+        config_quic.set_cc_algorithm(args.cc.to_quiche());
+        config_quic.set_max_idle_timeout(args.idle_timeout.as_millis() as u64);
+        config_quic.enable_dgram(true, DGRAM_QUEUE_LEN, DGRAM_QUEUE_LEN);
+
+        let mut scid_bytes = [0u8; quiche::MAX_CONN_ID_LEN];
+        rand::rng().fill_bytes(&mut scid_bytes);
+        let scid = quiche::ConnectionId::from_ref(&scid_bytes);
+
+        log::info!(
+            "opening tunnel to {} through proxy {}",
+            args.server_name,
+            proxy.remote_addr
+        );
+
+        let conn = quiche::connect(
+            Some(&proxy.server_name),
+            &scid,
+            socket.local_addr()?,
+            SocketAddr::V4(proxy.remote_addr),
+            &mut config_quic,
+        )?;
+
+        let mut tunnel = Self {
+            conn,
+            h3: None,
+            stream_id: None,
+            open: false,
+            closed: false,
+            buf: vec![0; args.mtu],
+            out: vec![0; args.mtu],
+            dgram: vec![0; args.mtu],
+        };
+
+        let started = Instant::now();
+        while !tunnel.open {
+            let remaining = SETUP_TIMEOUT.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                return Err(TunnelError::TimedOut.into());
+            }
+
+            tunnel.flush(socket)?;
+
+            let timeout = tunnel.timeout().map_or(remaining, |t| t.min(remaining));
+            poll.poll(events, Some(timeout))?;
+
+            tunnel.recv_from(socket)?;
+            if events.is_empty() {
+                tunnel.on_timeout();
+            }
+
+            if tunnel.conn.is_closed() && !tunnel.conn.is_established() {
+                return Err(
+                    TunnelError::Handshake(HandshakeError::from_closed(&tunnel.conn)).into(),
+                );
+            }
+            if tunnel.is_closed() {
+                return Err(TunnelError::Closed.into());
+            }
+
+            tunnel.request(proxy)?;
+        }
+
+        log::info!("proxy opened the tunnel");
+        Ok(tunnel)
+    }
+
+    // Sets up HTTP/3 once the handshake is done and sends the CONNECT request once the proxy's
+    // SETTINGS arrived, they tell whether it supports extended CONNECT and datagrams
+    fn request(&mut self, proxy: &config::Proxy) -> anyhow::Result<()> {
+        if !self.conn.is_established() || self.stream_id.is_some() {
+            return Ok(());
+        }
+
+        let h3 = match &mut self.h3 {
+            Some(h3) => h3,
+            None => {
+                let mut h3_config = quiche::h3::Config::new()?;
+                h3_config.enable_extended_connect(true);
+                let h3 = quiche::h3::Connection::with_transport(&mut self.conn, &h3_config)?;
+                self.h3.insert(h3)
+            }
+        };
+
+        // SETTINGS are read while polling events
+        loop {
+            match h3.poll(&mut self.conn) {
+                Ok(_) => {}
+                Err(quiche::h3::Error::Done) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if h3.peer_settings_raw().is_none() {
+            return Ok(());
+        }
+        if !h3.extended_connect_enabled_by_peer() || !h3.dgram_enabled_by_peer(&self.conn) {
+            return Err(TunnelError::Unsupported.into());
+        }
+
+        let headers = [
+            quiche::h3::Header::new(b":method", b"CONNECT"),
+            quiche::h3::Header::new(b":protocol", b"connect-udp"),
+            quiche::h3::Header::new(b":scheme", b"https"),
+            quiche::h3::Header::new(b":authority", proxy.authority.as_bytes()),
+            quiche::h3::Header::new(b":path", proxy.path.as_bytes()),
+            quiche::h3::Header::new(b"capsule-protocol", b"?1"),
+        ];
+        self.stream_id = Some(h3.send_request(&mut self.conn, &headers, false)?);
+
+        Ok(())
+    }
+
+    // Reads packets from the proxy and handles events on the CONNECT stream
+    pub fn recv_from(&mut self, socket: &mio::net::UdpSocket) -> anyhow::Result<()> {
+        let local = socket.local_addr()?;
+
+        loop {
+            let (len, from) = match socket.recv_from(&mut self.buf) {
+                Ok(v) => v,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            };
+
+            let recv_info = quiche::RecvInfo { to: local, from };
+            self.conn.recv(&mut self.buf[..len], recv_info)?;
+        }
+
+        let (Some(h3), Some(stream_id)) = (self.h3.as_mut(), self.stream_id) else {
+            return Ok(());
+        };
+
+        loop {
+            let (id, event) = match h3.poll(&mut self.conn) {
+                Ok(v) => v,
+                Err(quiche::h3::Error::Done) => break,
+                Err(e) => return Err(e.into()),
+            };
+            if id != stream_id {
+                continue;
+            }
+
+            match event {
+                quiche::h3::Event::Headers { list, .. } if !self.open => {
+                    let status = list
+                        .iter()
+                        .find(|h| h.name() == b":status")
+                        .map(|h| String::from_utf8_lossy(h.value()).into_owned())
+                        .unwrap_or_default();
+                    if !status.starts_with('2') {
+                        return Err(TunnelError::Refused(status).into());
+                    }
+                    self.open = true;
+                }
+
+                // capsules aren't used, but unread data would stall flow control
+                quiche::h3::Event::Data => {
+                    self.dgram.resize(self.buf.len(), 0);
+                    while h3.recv_body(&mut self.conn, id, &mut self.dgram).is_ok() {}
+                }
+
+                quiche::h3::Event::Finished | quiche::h3::Event::Reset(_) => {
+                    log::warn!("proxy closed the tunnel");
+                    self.closed = true;
+                }
+
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    // Sends a packet of the tunneled connection, dropping it when the proxy connection can't
+    // take it right now; QUIC recovers it like any other lost packet
+    pub fn send(&mut self, packet: &[u8]) -> anyhow::Result<()> {
+        let Some(stream_id) = self.stream_id.filter(|_| self.open) else {
+            return Ok(());
+        };
+
+        self.dgram.clear();
+        put_varint(&mut self.dgram, stream_id / 4);
+        put_varint(&mut self.dgram, UDP_PAYLOAD_CONTEXT);
+        self.dgram.extend_from_slice(packet);
+
+        match self.conn.dgram_send(&self.dgram) {
+            Ok(()) => Ok(()),
+            Err(quiche::Error::Done | quiche::Error::BufferTooShort) => {
+                log::debug!("dropping tunneled packet of {} bytes", packet.len());
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Next packet of the tunneled connection received from the proxy
+    pub fn recv(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let stream_id = self.stream_id?;
+
+        loop {
+            self.dgram.resize(self.buf.len(), 0);
+            let len = self.conn.dgram_recv(&mut self.dgram).ok()?;
+
+            let datagram = &self.dgram[..len];
+            let Some((quarter_id, datagram)) = get_varint(datagram) else {
+                continue;
+            };
+            let Some((context, payload)) = get_varint(datagram) else {
+                continue;
+            };
+            if quarter_id != stream_id / 4 || context != UDP_PAYLOAD_CONTEXT {
+                continue;
+            }
+            if payload.len() > buf.len() {
+                continue;
+            }
+
+            buf[..payload.len()].copy_from_slice(payload);
+            return Some(payload.len());
+        }
+    }
+
+    pub fn flush(&mut self, socket: &mio::net::UdpSocket) -> anyhow::Result<()> {
+        loop {
+            match self.conn.send(&mut self.out) {
+                Ok((write, send_info)) => match socket.send_to(&self.out[..write], send_info.to) {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                },
+                Err(quiche::Error::Done) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // Closes the connection to the proxy, which tears down the tunnel with it
+    pub fn close(&mut self, socket: &mio::net::UdpSocket) -> anyhow::Result<()> {
+        match self.conn.close(true, super::H3_NO_ERROR, b"") {
+            Ok(()) | Err(quiche::Error::Done) => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.flush(socket)
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.conn.timeout()
+    }
+
+    pub fn on_timeout(&mut self) {
+        self.conn.on_timeout();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed || self.conn.is_closed()
+    }
+}
+
+// QUIC variable-length integer encoding (RFC 9000, section 16)
+fn put_varint(buf: &mut Vec<u8>, v: u64) {
+    match v {
+        0..=0x3f => buf.push(v as u8),
+        0x40..=0x3fff => buf.extend_from_slice(&(v as u16 | 0x4000).to_be_bytes()),
+        0x4000..=0x3fff_ffff => buf.extend_from_slice(&(v as u32 | 0x8000_0000).to_be_bytes()),
+        _ => buf.extend_from_slice(&(v | 0xc000_0000_0000_0000).to_be_bytes()),
+    }
+}
+
+// Splits a varint off the front of buf
+fn get_varint(buf: &[u8]) -> Option<(u64, &[u8])> {
+    let len = 1 << (buf.first()? >> 6);
+    if buf.len() < len {
+        return None;
+    }
+
+    let mut v = u64::from(buf[0] & 0x3f);
+    for b in &buf[1..len] {
+        v = (v << 8) | u64::from(*b);
+    }
+
+    Some((v, &buf[len..]))
+}
@@ -1,51 +1,261 @@
 #![allow(dead_code)]
 
-use clap::Parser;
-use clap::arg;
-use std::net::{SocketAddrV4, ToSocketAddrs};
+use crate::FuzzError;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use std::net::{SocketAddr, SocketAddrV4, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
 
-pub const MAX_DATAGRAM_SIZE: usize = 1350;
+pub const MAX_DATAGRAM_SIZE: usize = 1350; // default MTU
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_MAX_DATA: u64 = 10_000_000;
+pub const DEFAULT_MAX_STREAMS: u64 = 100;
+pub const DEFAULT_MAX_ACK_DELAY: u64 = 25; // ms, quiche's and RFC 9000's default
+pub const MAX_QPACK_TABLE: u64 = 1 << 20; // browsers offer 4 KiB to 64 KiB
+pub const MAX_QPACK_BLOCKED: u64 = 1000;
+pub const DEFAULT_USER_AGENT: &str = concat!("fuzzh3/", env!("CARGO_PKG_VERSION"));
 
+#[derive(Clone)]
 pub struct QuicConfig {
     pub server_name: String,
     pub remote_addr: SocketAddrV4,
     pub verify_peer: bool,
+    pub max_body_size: Option<usize>, // body bytes kept per response, the rest is discarded
+    pub max_headers: Option<usize>,   // header fields kept per response, the rest is discarded
+    pub idle_timeout: Duration,
+    pub max_data: u64,                // connection-wide flow-control window
+    pub max_streams: u64,             // concurrent bidirectional streams the peer may open
+    pub qpack_max_table: Option<u64>, // advertised QPACK dynamic table capacity, quiche's 0 when unset
+    pub qpack_blocked: Option<u64>,   // advertised QPACK blocked streams, quiche's 0 when unset
+    pub max_ack_delay: u64,           // ms the client may hold back ACKs, advertised to the server
+    pub allow_migration: bool,        // let the connection move to another local address
+    pub ecn: bool,                    // mark packets ECT(0) and count the marks of received ones
+    pub cc: CongestionControl,
+    pub mtu: usize,                     // maximum UDP payload size
+    pub proxy: Option<Proxy>,           // MASQUE proxy the connection is tunneled through
+    pub bind: Option<SocketAddr>,       // local address, any interface and port when unset
+    pub session_file: Option<PathBuf>,  // session ticket cache entry of the target
+    pub stream_bodies: Option<PathBuf>, // response bodies are written here instead of kept in memory
+    pub datagram: bool,                 // offer QUIC datagrams (RFC 9221) in the handshake
+    pub quic_version: u32,              // version of the first Initial packet
 }
 
 impl QuicConfig {
-    pub fn new(url: &url::Url, verify_peer: bool) -> anyhow::Result<Self> {
+    pub fn new(url: &url::Url, no_verify: bool) -> Result<Self, FuzzError> {
         if let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) {
-            let remote_addr = resolve_ipv4(host, port)?[0];
-            Ok(QuicConfig {
-                server_name: host.to_string(),
-                remote_addr,
-                verify_peer: !verify_peer,
-            })
+            let remote_addr = *resolve_ipv4(host, port)
+                .map_err(|source| FuzzError::Resolve {
+                    host: host.to_string(),
+                    source,
+                })?
+                .first()
+                .ok_or_else(|| FuzzError::NoAddress(host.to_string()))?;
+
+            Ok(Self::with_remote(host, remote_addr, no_verify))
         } else {
-            anyhow::bail!("URL missing host or port");
+            Err(FuzzError::InvalidUrl(
+                "URL missing host or port".to_string(),
+            ))
+        }
+    }
+
+    // Connects through a MASQUE proxy, which resolves the target itself
+    pub fn via_proxy(url: &url::Url, no_verify: bool, proxy: &str) -> Result<Self, FuzzError> {
+        let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+            return Err(FuzzError::InvalidUrl(
+                "URL missing host or port".to_string(),
+            ));
+        };
+        let proxy = Proxy::new(proxy, host, port)?;
+
+        // packets of the tunneled connection arrive from the proxy
+        let mut config = Self::with_remote(host, proxy.remote_addr, no_verify);
+        config.proxy = Some(proxy);
+        Ok(config)
+    }
+
+    fn with_remote(server_name: &str, remote_addr: SocketAddrV4, no_verify: bool) -> Self {
+        QuicConfig {
+            server_name: server_name.to_string(),
+            remote_addr,
+            verify_peer: !no_verify,
+            max_body_size: None,
+            max_headers: None,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            max_data: DEFAULT_MAX_DATA,
+            max_streams: DEFAULT_MAX_STREAMS,
+            qpack_max_table: None,
+            qpack_blocked: None,
+            max_ack_delay: DEFAULT_MAX_ACK_DELAY,
+            allow_migration: false,
+            ecn: false,
+            cc: CongestionControl::default(),
+            mtu: MAX_DATAGRAM_SIZE,
+            proxy: None,
+            bind: None,
+            session_file: None,
+            stream_bodies: None,
+            datagram: false,
+            quic_version: quiche::PROTOCOL_VERSION,
         }
     }
 }
 
+// CONNECT-UDP template used when the proxy URL has no path
+const DEFAULT_PROXY_PATH: &str = "/.well-known/masque/udp/{target_host}/{target_port}/";
+
+// MASQUE proxy which forwards the connection to the target
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    pub server_name: String,
+    pub remote_addr: SocketAddrV4,
+    pub authority: String, // host:port of the proxy
+    pub path: String,      // CONNECT-UDP path with the target filled in
+}
+
+impl Proxy {
+    // `url` is either the proxy's full CONNECT-UDP URL for this target, or a URI template with
+    // {target_host} and {target_port}, e.g. https://proxy/masque/{target_host}/{target_port}/
+    pub fn new(url: &str, target_host: &str, target_port: u16) -> Result<Self, FuzzError> {
+        let invalid = |reason: String| FuzzError::InvalidUrl(format!("proxy {url}: {reason}"));
+        let parsed = url::Url::parse(url).map_err(|e| invalid(e.to_string()))?;
+        if parsed.scheme() != "https" {
+            return Err(invalid("MASQUE proxies are reached over https".to_string()));
+        }
+        let (Some(host), Some(port)) = (parsed.host_str(), parsed.port_or_known_default()) else {
+            return Err(invalid("missing host".to_string()));
+        };
+
+        // the parser percent-encodes the template's braces
+        let template = percent_encoding::percent_decode_str(parsed.path()).decode_utf8_lossy();
+        let template = if template == "/" {
+            DEFAULT_PROXY_PATH
+        } else {
+            &template
+        };
+        let path = template
+            .replace("{target_host}", target_host)
+            .replace("{target_port}", &target_port.to_string());
+
+        let remote_addr = *resolve_ipv4(host, port)
+            .map_err(|source| FuzzError::Resolve {
+                host: host.to_string(),
+                source,
+            })?
+            .first()
+            .ok_or_else(|| FuzzError::NoAddress(host.to_string()))?;
+
+        Ok(Proxy {
+            server_name: host.to_string(),
+            remote_addr,
+            authority: format!("{host}:{port}"),
+            path,
+        })
+    }
+}
+
 #[derive(Parser, Debug)]
-#[command(about, long_about = None)]
+#[command(
+    about,
+    long_about = None,
+    args_override_self = true,
+    after_help = "HTTP/3 server push isn't supported: quiche never sends MAX_PUSH_ID, so servers \
+                  may not push, and it drops PUSH_PROMISE frames unseen"
+)]
 /// QUIC/HTTP3 fuzzer
 pub struct Args {
-    #[arg(short, long, value_name = "URL")]
+    #[arg(long, value_name = "PATH")]
+    /// Load options from TOML file, flags given on the command line take precedence
+    pub config: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        value_name = "URL",
+        required_unless_present = "target_file"
+    )]
     /// URL to connect to
-    pub url: String,
+    pub url: Option<String>,
+
+    #[arg(
+        long = "target-file",
+        value_name = "PATH",
+        conflicts_with_all = ["url", "ports", "dry_run", "replay", "resume", "output_har", "stats_json"]
+    )]
+    /// Scan each URL listed in this file in turn, one per line
+    pub target_file: Option<String>,
 
     #[arg(short, long, default_value_t = 443)]
     /// Target port
     pub port: u16,
 
+    #[arg(
+        long,
+        value_name = "PORT,...",
+        value_delimiter = ',',
+        conflicts_with_all = ["resume", "output_har", "stats_json", "replay"]
+    )]
+    /// Scan each of these ports of the URL's host in turn instead of the URL's port
+    pub ports: Vec<u16>,
+
+    #[arg(
+        long,
+        value_name = "OTHER_URL",
+        conflicts_with_all = ["target_file", "ports", "dry_run", "replay", "resume", "output_har", "stats_json"]
+    )]
+    /// Scan this URL too and only report paths whose status or size differ between the two
+    pub diff: Option<String>,
+
     #[arg(long = "no-verify", default_value_t = false)]
     /// Don't verify server's certificate
     pub no_verify: bool,
 
-    #[arg(short, long)]
-    /// Path to wordlist
-    pub wordlist: String,
+    #[arg(short, long, required_unless_present_any = ["replay", "brute", "check", "datagram"])]
+    /// Path to wordlist, or an http(s) URL to download it from
+    pub wordlist: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "CHARSET",
+        requires = "length",
+        conflicts_with_all = ["wordlist", "shuffle", "mutate", "transform", "add_slash"]
+    )]
+    /// Try every string of --length characters from CHARSET instead of a wordlist
+    pub brute: Option<String>,
+
+    #[arg(long, value_name = "N", requires = "brute", value_parser = clap::value_parser!(u32).range(1..))]
+    /// Length of the strings generated by --brute
+    pub length: Option<u32>,
+
+    #[arg(
+        long = "strict-encoding",
+        default_value_t = false,
+        conflicts_with = "brute"
+    )]
+    /// Abort on wordlist lines which aren't UTF-8 instead of reading them as Latin-1
+    pub strict_encoding: bool,
+
+    #[arg(long, value_name = "PATH", conflicts_with = "dry_run")]
+    /// Request a single path and print the whole response instead of scanning
+    pub replay: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["replay", "dry_run", "diff", "target_file", "ports"]
+    )]
+    /// Request / once and print its status to check the target speaks HTTP/3, without scanning
+    pub check: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["check", "replay", "dry_run", "diff", "target_file", "ports", "proxy"]
+    )]
+    /// Send one QUIC datagram and report whether the server supports them and replies, without scanning
+    pub datagram: bool,
 
     #[arg(short, long, default_value = "get")]
     /// HTTP method
@@ -55,6 +265,111 @@ pub struct Args {
     /// Include headers in request
     pub headers: Vec<String>,
 
+    #[arg(long = "headers-file", value_name = "CSV")]
+    /// Extra headers per word, one "word,Name: value,..." line each
+    pub headers_file: Option<String>,
+
+    #[arg(long = "cookie", value_name = "NAME=VALUE; ...", action = clap::ArgAction::Append)]
+    /// Send cookies, multiple flags are joined into one cookie header
+    pub cookies: Vec<String>,
+
+    #[arg(long = "form", value_name = "NAME=VALUE", action = clap::ArgAction::Append)]
+    /// POST a multipart/form-data field, NAME=@PATH uploads a file; FUZZ works in values
+    pub form: Vec<String>,
+
+    #[arg(long = "fuzz-query", default_value_t = false)]
+    /// Put words in place of FUZZ in the URL's query string instead of the path
+    pub fuzz_query: bool,
+
+    #[arg(
+        long = "request-file",
+        value_name = "PATH",
+        conflicts_with = "fuzz_query"
+    )]
+    /// Send the request in this file, with FUZZ in its path or header values
+    pub request_file: Option<String>,
+
+    #[arg(long = "force-scheme", default_value_t = false)]
+    /// Send URLs which aren't https as given instead of refusing them
+    pub force_scheme: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Percent-encode words before putting them into the path
+    pub urlencode: bool,
+
+    #[arg(long = "double-encode", default_value_t = false)]
+    /// Percent-encode words twice, e.g. for testing WAF decoding
+    pub double_encode: bool,
+
+    #[arg(long, value_enum, value_name = "CASE", action = clap::ArgAction::Append)]
+    /// Also try upper, lower or capitalized variants of each word (repeatable)
+    pub transform: Vec<Transform>,
+
+    #[arg(long = "add-slash", default_value_t = false)]
+    /// Also try each word with a trailing slash
+    pub add_slash: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Also try mutations of each word: backup suffixes (~, .bak, .old) and single character edits
+    pub mutate: bool,
+
+    #[arg(
+        long = "mutate-max",
+        value_name = "N",
+        default_value_t = 20,
+        requires = "mutate"
+    )]
+    /// Most mutations tried per word
+    pub mutate_max: usize,
+
+    #[arg(long, default_value_t = false, conflicts_with = "resume")]
+    /// Try words in random order
+    pub shuffle: bool,
+
+    #[arg(long, value_name = "N")]
+    /// Seed for all randomness (word order, delay jitter, probe paths), to repeat a run
+    pub seed: Option<u64>,
+
+    #[arg(long = "detect-404", default_value_t = false)]
+    /// Probe a random path first and drop responses identical to it
+    pub detect_404: bool,
+
+    #[arg(long = "follow-redirects", default_value_t = false)]
+    /// Follow redirects of matched responses on the same host and report where they end
+    pub follow_redirects: bool,
+
+    #[arg(long = "max-redirects", value_name = "N", default_value_t = 10)]
+    /// Maximum number of redirects followed per word
+    pub max_redirects: usize,
+
+    #[arg(
+        long = "match-redirects",
+        value_name = "MIN-MAX",
+        requires = "follow_redirects"
+    )]
+    /// Only report responses reached through this many redirects (e.g. 2-10)
+    pub match_redirects: Option<String>,
+
+    #[arg(long = "detect-open-redirect", default_value_t = false)]
+    /// Report redirects to another host taken from the word, e.g. with ?next=FUZZ, as open redirects
+    pub detect_open_redirect: bool,
+
+    #[arg(long, value_name = "HOST", action = clap::ArgAction::Append)]
+    /// Don't report redirects to this host as open redirects (repeatable). They aren't followed, the connection only serves the target
+    pub scope: Vec<String>,
+
+    #[arg(long = "user-agent", value_name = "AGENT", default_value = DEFAULT_USER_AGENT)]
+    /// User-Agent header sent with every request
+    pub user_agent: String,
+
+    #[arg(long, value_name = "USER:PASS")]
+    /// Send basic authentication credentials
+    pub user: Option<String>,
+
+    #[arg(long, value_name = "TOKEN", conflicts_with = "user")]
+    /// Send an Authorization: Bearer header, unless -H already sets Authorization
+    pub bearer: Option<String>,
+
     /// Match HTTP status codes (e.g. 200, 200-299)
     #[arg(
         long = "match-code",
@@ -66,6 +381,265 @@ pub struct Args {
     /// Match response body size (e.g. 0-1024)
     #[arg(long = "match-size", value_name = "MIN-MAX")]
     pub match_size: Option<String>,
+
+    /// Match only these exact body sizes (e.g. 0,1024,4096)
+    #[arg(
+        long = "match-size-list",
+        value_name = "SIZE,...",
+        value_delimiter = ','
+    )]
+    pub match_size_list: Vec<usize>,
+
+    /// Drop responses with any of these exact body sizes
+    #[arg(
+        long = "filter-size-list",
+        value_name = "SIZE,...",
+        value_delimiter = ','
+    )]
+    pub filter_size_list: Vec<usize>,
+
+    /// Match bodies containing any line of this file, lines wrapped in /.../ are regexes
+    #[arg(long = "match-grep-file", value_name = "PATH")]
+    pub match_grep_file: Option<String>,
+
+    /// Match JSON responses whose field at PATH (e.g. data.status) equals VALUE (repeatable)
+    #[arg(long = "match-json", value_name = "PATH=VALUE", action = clap::ArgAction::Append)]
+    pub match_json: Vec<String>,
+
+    /// Match only responses with a body
+    #[arg(long = "match-nonempty", conflicts_with = "match_empty")]
+    pub match_nonempty: bool,
+
+    /// Match only responses without a body
+    #[arg(long = "match-empty")]
+    pub match_empty: bool,
+
+    /// Output line template (tokens: status, path, size, words, lines, time, title, redirects, header:NAME)
+    #[arg(long, value_name = "TEMPLATE", default_value = crate::fuzz::output::DEFAULT_FORMAT)]
+    pub format: String,
+
+    #[arg(long = "show-title", default_value_t = false)]
+    /// Append the <title> of HTML responses to result lines
+    pub show_title: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Measure throughput without matching or printing responses
+    pub bench: bool,
+
+    #[arg(long = "dry-run", default_value_t = false)]
+    /// Print the requests that would be sent, without connecting
+    pub dry_run: bool,
+
+    #[arg(short, long, default_value_t = false)]
+    /// Print only matched paths, without progress bar or logs
+    pub silent: bool,
+
+    #[arg(short, long, default_value_t = false)]
+    /// Print response headers of matched responses
+    pub verbose: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Print the negotiated protocol and peer certificate before scanning
+    pub info: bool,
+
+    #[arg(long = "no-progress", default_value_t = false)]
+    /// Hide the progress bar, progress is logged at info level instead
+    pub no_progress: bool,
+
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    /// Colorize status codes and logs; auto colors terminals unless NO_COLOR is set
+    pub color: ColorChoice,
+
+    #[arg(long = "no-color", default_value_t = false)]
+    /// Same as --color never
+    pub no_color: bool,
+
+    /// Keep at most this many body bytes per response
+    #[arg(long = "max-body-size", value_name = "BYTES")]
+    pub max_body_size: Option<usize>,
+
+    /// Keep at most N headers (and N trailers) per response
+    #[arg(long = "max-headers", value_name = "N")]
+    pub max_headers: Option<usize>,
+
+    /// Write response bodies into directory as they arrive instead of keeping them in memory
+    #[arg(long = "stream-bodies", value_name = "DIR")]
+    pub stream_bodies: Option<PathBuf>,
+
+    #[arg(long = "max-inflight-bytes", value_name = "BYTES")]
+    /// Stop sending new requests while in-flight responses hold this many body bytes
+    pub max_inflight_bytes: Option<usize>,
+
+    #[arg(short, long, value_name = "PATH")]
+    /// Also write result lines to file
+    pub output: Option<String>,
+
+    #[arg(long, default_value_t = false, requires = "output")]
+    /// Append to the --output file instead of overwriting it
+    pub append: bool,
+
+    /// Write matches as CSV to file
+    #[arg(long = "output-csv", value_name = "PATH")]
+    pub output_csv: Option<String>,
+
+    #[arg(long = "metrics-addr", value_name = "ADDR:PORT")]
+    /// Serve scan counters for Prometheus at http://ADDR:PORT/metrics
+    pub metrics_addr: Option<SocketAddr>,
+
+    #[arg(long = "output-sqlite", value_name = "PATH")]
+    /// Insert matches into the results table of a SQLite database, created when missing
+    pub output_sqlite: Option<String>,
+
+    #[arg(long = "output-har", value_name = "PATH")]
+    /// Write matches as an HTTP Archive (HAR) to file
+    pub output_har: Option<String>,
+
+    #[arg(long = "stats-json", value_name = "PATH")]
+    /// Write the request counts and QUIC transport stats of the scan to a JSON file once it ends
+    pub stats_json: Option<String>,
+
+    #[arg(long = "request-timeout", value_name = "SECS")]
+    /// Give up on requests which don't complete in time
+    pub request_timeout: Option<u64>,
+
+    #[arg(long = "session-dir", value_name = "DIR")]
+    /// Cache TLS session tickets here, one per host:port, to resume later connections
+    pub session_dir: Option<PathBuf>,
+
+    #[arg(long, value_name = "ADDR:PORT")]
+    /// Send from this local address and port
+    pub bind: Option<SocketAddr>,
+
+    #[arg(long, value_name = "URL")]
+    /// Tunnel the connection through a MASQUE CONNECT-UDP proxy; the path may use
+    /// {target_host} and {target_port}, the well-known path is used without one
+    pub proxy: Option<String>,
+
+    #[arg(long, default_value_t = false, conflicts_with = "proxy")]
+    /// Ask the server over HTTPS (TCP) which port it serves HTTP/3 on, from its Alt-Svc header
+    pub discover: bool,
+
+    #[arg(long = "max-requests", value_name = "N")]
+    /// Stop sending after N requests and wait for their responses
+    pub max_requests: Option<u64>,
+
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    /// Retry requests which were reset or timed out up to N times
+    pub retries: u32,
+
+    #[arg(long = "connect-retries", value_name = "N", default_value_t = 0)]
+    /// Retry a failed initial connection up to N times
+    pub connect_retries: u32,
+
+    #[arg(long = "connect-backoff", value_name = "MS", default_value_t = 500)]
+    /// Wait this long before the first connection retry, twice as long before each further one
+    pub connect_backoff: u64,
+
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    /// Wait at least this long between sending two requests
+    pub delay: u64,
+
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    /// Add a random extra wait of up to this long to every delay
+    pub jitter: u64,
+
+    #[arg(long, default_value_t = false)]
+    /// Lower the number of in-flight requests when the server responds with 429/503
+    pub adaptive: bool,
+
+    #[arg(long = "auto-concurrency", default_value_t = false)]
+    /// Tune the number of in-flight requests to the measured RTT and throughput
+    pub auto_concurrency: bool,
+
+    #[arg(
+        long = "abort-on-errors",
+        value_name = "PCT",
+        value_parser = clap::value_parser!(u8).range(1..=100)
+    )]
+    /// Abort the scan when more than PCT% of the last 100 responses are 5xx
+    pub abort_on_errors: Option<u8>,
+
+    #[arg(long = "idle-timeout", value_name = "SECS", default_value_t = DEFAULT_IDLE_TIMEOUT.as_secs())]
+    /// Close the connection after this long without activity, 0 disables it
+    pub idle_timeout: u64,
+
+    #[arg(long = "max-data", value_name = "BYTES", default_value_t = DEFAULT_MAX_DATA)]
+    /// Initial connection-wide flow-control window
+    pub max_data: u64,
+
+    #[arg(long = "max-streams", value_name = "N", default_value_t = DEFAULT_MAX_STREAMS)]
+    /// Initial limit of concurrent bidirectional streams
+    pub max_streams: u64,
+
+    #[arg(
+        long = "qpack-max-table",
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u64).range(0..=MAX_QPACK_TABLE)
+    )]
+    /// QPACK dynamic table capacity offered to the server, 0 (the default) to 1 MiB
+    pub qpack_max_table: Option<u64>,
+
+    #[arg(
+        long = "qpack-blocked",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(0..=MAX_QPACK_BLOCKED)
+    )]
+    /// Streams the server may block on QPACK table updates, 0 (the default) to 1000
+    pub qpack_blocked: Option<u64>,
+
+    #[arg(
+        long = "max-ack-delay",
+        value_name = "MS",
+        default_value_t = DEFAULT_MAX_ACK_DELAY,
+        value_parser = clap::value_parser!(u64).range(0..=1000)
+    )]
+    /// Longest the client may delay acknowledging packets, 0 to 1000
+    pub max_ack_delay: u64,
+
+    #[arg(
+        long = "allow-migration",
+        default_value_t = false,
+        conflicts_with = "proxy"
+    )]
+    /// Don't forbid connection migration in the transport parameters
+    pub allow_migration: bool,
+
+    #[arg(long = "migrate-after", value_name = "N", requires = "allow_migration")]
+    /// Move the connection to a new local port after N responses, to test the server's migration handling
+    pub migrate_after: Option<u64>,
+
+    #[arg(long = "ecn", default_value_t = false, conflicts_with = "proxy")]
+    /// Mark packets ECT(0) and report the ECN marks of received packets after the scan
+    pub ecn: bool,
+
+    #[arg(long = "quic-version", value_name = "HEX")]
+    /// QUIC version to offer first, a reserved one (0x?a?a?a?a) forces version negotiation
+    pub quic_version: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = CongestionControl::default())]
+    /// Congestion control algorithm
+    pub cc: CongestionControl,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = MAX_DATAGRAM_SIZE as u16,
+        value_parser = clap::value_parser!(u16).range(1200..=1500)
+    )]
+    /// Maximum UDP payload size
+    pub mtu: u16,
+
+    #[arg(long = "no-reconnect", default_value_t = false)]
+    /// Stop the scan instead of reconnecting after GOAWAY or a closed connection
+    pub no_reconnect: bool,
+
+    /// Persist scan progress to file and resume from it on restart
+    #[arg(long, value_name = "STATE_FILE")]
+    pub resume: Option<String>,
+
+    /// Save bodies of matched responses into directory
+    #[arg(long = "save-bodies", value_name = "DIR")]
+    pub save_bodies: Option<String>,
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -76,7 +650,213 @@ pub enum Method {
     Delete,
 }
 
-fn resolve_ipv4(host: &str, port: u16) -> anyhow::Result<Vec<SocketAddrV4>> {
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Transform {
+    Upper,
+    Lower,
+    Capitalize,
+}
+
+impl Transform {
+    pub fn apply(self, word: &str) -> String {
+        match self {
+            Transform::Upper => word.to_uppercase(),
+            Transform::Lower => word.to_lowercase(),
+            Transform::Capitalize => {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first
+                        .to_uppercase()
+                        .chain(chars.flat_map(char::to_lowercase))
+                        .collect(),
+                    None => String::new(),
+                }
+            }
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum CongestionControl {
+    #[default]
+    Cubic,
+    Reno,
+    Bbr,
+}
+
+impl CongestionControl {
+    pub fn to_quiche(self) -> quiche::CongestionControlAlgorithm {
+        match self {
+            CongestionControl::Cubic => quiche::CongestionControlAlgorithm::CUBIC,
+            CongestionControl::Reno => quiche::CongestionControlAlgorithm::Reno,
+            CongestionControl::Bbr => quiche::CongestionControlAlgorithm::Bbr2Gcongestion,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    // Whether to emit ANSI colors on stream, the one place this is decided
+    pub fn enabled(self, stream: &impl IsTerminal) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // https://no-color.org: set and not empty
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && stream.is_terminal()
+            }
+        }
+    }
+}
+
+impl Args {
+    // --no-color predates --color and wins over it
+    pub fn color(&self) -> ColorChoice {
+        if self.no_color {
+            ColorChoice::Never
+        } else {
+            self.color
+        }
+    }
+
+    // Parses command line arguments, taking defaults from the --config file if one is given.
+    //
+    // Keys in the file are Args field names (e.g. `url`, `no_verify`, `match_codes`) and are
+    // turned into flags placed before the real command line, so single-value flags given on
+    // the command line override the file and list flags (headers, match codes) extend it. On/off
+    // flags the file turns on are turned off again by their counterpart, see `command`
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_from(std::env::args_os().collect()).map_err(|e| {
+            match e.downcast::<clap::Error>() {
+                // usage errors and --help print and exit the way clap does by itself
+                Ok(e) => e.exit(),
+                Err(e) => e,
+            }
+        })
+    }
+
+    fn load_from(cli: Vec<OsString>) -> anyhow::Result<Self> {
+        let args = match config_path(&cli) {
+            Some(path) => {
+                let mut args = cli[..1].to_vec();
+                args.extend(config_file_args(&path)?);
+                args.extend_from_slice(&cli[1..]);
+                args
+            }
+            None => cli,
+        };
+
+        let matches = parser().try_get_matches_from(args)?;
+        Ok(Self::from_arg_matches(&matches)?)
+    }
+}
+
+// Args' parser with a hidden counterpart for every on/off flag, --no-X for --X and --X for
+// --no-X. Whichever of the two comes last wins
+fn parser() -> clap::Command {
+    let command = Args::command();
+    let longs: Vec<String> = command
+        .get_arguments()
+        .filter_map(|arg| arg.get_long().map(str::to_string))
+        .collect();
+    let counterparts: Vec<(String, String)> = command
+        .get_arguments()
+        .filter(|arg| matches!(arg.get_action(), clap::ArgAction::SetTrue))
+        .filter_map(|arg| {
+            let long = arg.get_long()?;
+            let counterpart = match long.strip_prefix("no-") {
+                Some(long) => long.to_string(),
+                None => format!("no-{long}"),
+            };
+            // e.g. --no-color, whose counterpart would be --color, which takes a value
+            (!longs.contains(&counterpart)).then(|| (arg.get_id().to_string(), counterpart))
+        })
+        .collect();
+
+    counterparts
+        .into_iter()
+        .fold(command, |command, (id, counterpart)| {
+            command.arg(
+                clap::Arg::new(format!("{id}__counterpart"))
+                    .long(counterpart)
+                    .action(clap::ArgAction::SetTrue)
+                    .overrides_with(id)
+                    .hide(true),
+            )
+        })
+}
+
+fn config_path(cli: &[OsString]) -> Option<String> {
+    let mut iter = cli.iter().skip(1).map(|a| a.to_string_lossy());
+
+    while let Some(arg) = iter.next() {
+        if arg == "--config" {
+            return iter.next().map(|p| p.into_owned());
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+
+    None
+}
+
+// Converts TOML config file entries into command line flags
+fn config_file_args(path: &str) -> anyhow::Result<Vec<OsString>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read config file {path}: {e}"))?;
+    let table: toml::Table = content
+        .parse()
+        .map_err(|e| anyhow::anyhow!("invalid config file {path}: {e}"))?;
+
+    let command = Args::command();
+    let mut args = Vec::new();
+
+    for (key, value) in table {
+        let arg = command
+            .get_arguments()
+            .find(|a| a.get_id() == key.as_str() && key != "config")
+            .ok_or_else(|| anyhow::anyhow!("unknown key in config file: {key}"))?;
+
+        let flag = match (arg.get_long(), arg.get_short()) {
+            (Some(long), _) => format!("--{long}"),
+            (None, Some(short)) => format!("-{short}"),
+            (None, None) => anyhow::bail!("key {key} can't be set from config file"),
+        };
+
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+
+        for value in values {
+            match value {
+                toml::Value::Boolean(true) => args.push(flag.clone().into()),
+                toml::Value::Boolean(false) => {}
+                toml::Value::String(s) => {
+                    args.push(flag.clone().into());
+                    args.push(s.into());
+                }
+                toml::Value::Integer(_) | toml::Value::Float(_) => {
+                    args.push(flag.clone().into());
+                    args.push(value.to_string().into());
+                }
+                _ => anyhow::bail!("unsupported value for {key} in config file"),
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+fn resolve_ipv4(host: &str, port: u16) -> std::io::Result<Vec<SocketAddrV4>> {
     let addrs = (host, port).to_socket_addrs()?;
 
     let v4_addrs = addrs
@@ -88,3 +868,75 @@ fn resolve_ipv4(host: &str, port: u16) -> anyhow::Result<Vec<SocketAddrV4>> {
 
     Ok(v4_addrs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `name` keeps the files of tests running in parallel apart
+    fn load_with_config(name: &str, config: &str, cli: &[&str]) -> Args {
+        let path = std::env::temp_dir().join(format!("fuzzh3-{name}-{}.toml", std::process::id()));
+        std::fs::write(&path, config).unwrap();
+
+        let mut args: Vec<OsString> = vec!["fuzzh3".into(), "--config".into(), path.clone().into()];
+        args.extend(cli.iter().map(OsString::from));
+        let args = Args::load_from(args).unwrap();
+        let _ = std::fs::remove_file(path);
+        args
+    }
+
+    const CONFIG: &str = r#"
+        url = "https://file.example"
+        wordlist = "file.txt"
+        no_verify = true
+        follow_redirects = true
+        headers = ["x-file: 1"]
+    "#;
+
+    #[test]
+    fn config_file_sets_defaults() {
+        let args = load_with_config("defaults", CONFIG, &[]);
+        assert_eq!(args.url.as_deref(), Some("https://file.example"));
+        assert_eq!(args.wordlist.as_deref(), Some("file.txt"));
+        assert!(args.no_verify);
+        assert!(args.follow_redirects);
+        assert_eq!(args.headers, ["x-file: 1"]);
+    }
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let cli = [
+            "-u",
+            "https://cli.example",
+            "-w",
+            "cli.txt",
+            "--verify",
+            "--no-follow-redirects",
+            "-H",
+            "x-cli: 2",
+        ];
+        let args = load_with_config("overrides", CONFIG, &cli);
+        assert_eq!(args.url.as_deref(), Some("https://cli.example"));
+        assert_eq!(args.wordlist.as_deref(), Some("cli.txt"));
+        assert!(!args.no_verify);
+        assert!(!args.follow_redirects);
+        // list options combine, the file's values first
+        assert_eq!(args.headers, ["x-file: 1", "x-cli: 2"]);
+    }
+
+    #[test]
+    fn last_of_a_flag_and_its_counterpart_wins() {
+        let args = load_with_config(
+            "last-on",
+            CONFIG,
+            &["--no-follow-redirects", "--follow-redirects"],
+        );
+        assert!(args.follow_redirects);
+        let args = load_with_config(
+            "last-off",
+            CONFIG,
+            &["--follow-redirects", "--no-follow-redirects"],
+        );
+        assert!(!args.follow_redirects);
+    }
+}
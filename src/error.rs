@@ -0,0 +1,38 @@
+// Errors returned by the library API
+#[derive(thiserror::Error, Debug)]
+pub enum FuzzError {
+    #[error("invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("failed to resolve {host}")]
+    Resolve {
+        host: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("no IPv4 address found for {0}")]
+    NoAddress(String),
+    #[error("failed to bind {addr}")]
+    Bind {
+        addr: std::net::SocketAddr,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{host} doesn't appear to support HTTP/3")]
+    NoHttp3 {
+        host: String,
+        #[source]
+        source: crate::client::HandshakeError,
+    },
+    #[error("QUIC handshake failed")]
+    Handshake(#[source] anyhow::Error),
+    #[error("HTTP/3 negotiation failed")]
+    H3(#[source] anyhow::Error),
+    #[error("failed to read wordlist {path}")]
+    Wordlist {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("scan failed")]
+    Scan(#[source] anyhow::Error),
+}
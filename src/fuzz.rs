@@ -1,18 +1,100 @@
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use quiche::h3::NameValue;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 
-use crate::client::{self, ClientError, http};
-use std::collections::VecDeque;
+use crate::client::{self, ClientError, StreamError, http};
+use crate::config;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use std::{fs::File, io::BufRead, io::BufReader};
 
 use std::io::Read;
 use std::io::Write;
 
+pub mod breaker;
+pub mod brute;
+pub mod checkpoint;
+pub mod concurrency;
+pub mod metrics;
+pub mod output;
+pub mod summary;
+pub mod throttle;
+
+// How often progress is logged when the progress bar is hidden
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// Reconnect attempts after the connection closed, waiting twice as long before each one. Also
+// how often a word a connection dropped unprocessed is sent again
+const RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(500);
+
+// Brute-force words generated ahead of sending
+const FEED_AHEAD: usize = 1000;
+
+pub type ResponseCallback = Box<dyn FnMut(&http::Response)>;
+
+// Matched response together with the wordlist entry which produced it
+pub struct FuzzResult {
+    pub word: String,
+    pub response: http::Response,
+}
+
 pub struct Fuzzer {
-    pub matcher: Matcher,
-    reader: BufReader<File>,
+    pub matcher: Box<dyn ResponseMatcher>,
+    // Called for every completed response before matching. Runs on the fuzzing thread
+    // between network polls, so it should be cheap
+    pub on_response: Option<ResponseCallback>,
+    pub format: Option<output::Format>, // None when matches aren't printed to stdout
+    pub collect: bool,                  // return matches from fuzz()
+    pub verbose: bool,
+    pub out: output::Tee, // result lines
+    pub csv: Option<output::CsvWriter>,
+    pub sqlite: Option<output::SqliteWriter>,
+    pub metrics: Option<metrics::Metrics>,
+    pub har: Option<output::HarWriter>,
+    pub stats_json: Option<output::StatsWriter>,
+    pub bodies: Option<output::BodySaver>,
+    pub checkpoint: Option<checkpoint::Checkpoint>,
+    pub show_summary: bool,
+    pub bench: bool,           // print throughput instead of the summary
+    pub strict_encoding: bool, // abort on wordlist lines which aren't UTF-8 instead of decoding them as Latin-1
+    pub summary: summary::Summary,
+    pub interrupted: Arc<AtomicBool>, // set on Ctrl-C, stops sending new requests
+    pub reconnect: bool,              // reconnect and continue after GOAWAY or a closed connection
+    pub request_timeout: Option<Duration>,
+    pub retries: u32,              // attempts per word after a retryable failure
+    pub max_requests: Option<u64>, // requests sent before the scan stops, redirects included
+    pub throttle: Option<throttle::Throttle>,
+    pub concurrency: Option<concurrency::AutoConcurrency>, // in-flight cap following the link
+    pub breaker: Option<breaker::Breaker>, // aborts the scan when too many responses are 5xx
+    pub delay: Duration, // minimum pause between sends, independent of the throttle
+    pub jitter: Duration, // upper bound of the random time added to each delay
+    pub rng: rand::rngs::StdRng, // draws the jitter
+    next_send: Option<Instant>, // earliest time the next request may be sent
+    pub max_inflight_bytes: Option<usize>, // body bytes buffered across streams before pausing sends
+    pub max_redirects: usize,              // redirects followed per word, 0 disables following
+    pub match_redirects: Option<RangeInclusive<usize>>, // length of the redirect chains reported
+    pub detect_open_redirect: bool, // report redirects off the target to a host taken from the word
+    pub migrate_after: Option<u64>, // responses after which the connection moves to a new local port
+    pub scope: Vec<String>,         // hosts besides the target which redirects may safely lead to
+    pub shuffle: Option<u64>,       // seed for randomizing word order, None keeps wordlist order
+    pub variants: Variants,
+    pub word_headers: HashMap<String, Vec<(String, String)>>, // merged into the requests of a word
+    source: Source,
     client: client::Client,
-    progress: ProgressBar,
+    total: u64,                        // number of words in wordlist
+    pub progress: Option<ProgressBar>, // None when the progress bar is hidden
+}
+
+// Where the words of a scan come from
+enum Source {
+    Wordlist(Box<dyn BufRead>),
+    Brute(brute::Brute),
 }
 
 impl Fuzzer {
@@ -21,74 +103,1039 @@ impl Fuzzer {
 
         let total = count_lines(wordlist_path)?;
 
-        let file = File::open(wordlist_path)?;
-        let reader = BufReader::new(file);
+        let reader = open_wordlist(wordlist_path)?;
 
-        let progress = ProgressBar::new(total);
+        Ok(Self::with_source(client, Source::Wordlist(reader), total))
+    }
+
+    // Scans every word of a brute-force space instead of a wordlist
+    pub fn with_brute(client: client::Client, brute: brute::Brute) -> Self {
+        let total = brute.total();
+        Self::with_source(client, Source::Brute(brute), total)
+    }
+
+    fn with_source(client: client::Client, source: Source, total: u64) -> Self {
+        let matcher = Box::new(Matcher::default());
+
+        Self {
+            source,
+            client,
+            matcher,
+            on_response: None,
+            format: None,
+            collect: true,
+            verbose: false,
+            out: output::Tee::default(),
+            csv: None,
+            sqlite: None,
+            metrics: None,
+            har: None,
+            stats_json: None,
+            bodies: None,
+            checkpoint: None,
+            show_summary: false,
+            bench: false,
+            strict_encoding: false,
+            summary: summary::Summary::default(),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            reconnect: true,
+            request_timeout: None,
+            retries: 0,
+            max_requests: None,
+            throttle: None,
+            concurrency: None,
+            breaker: None,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            rng: rand::rngs::StdRng::from_os_rng(),
+            next_send: None,
+            max_inflight_bytes: None,
+            max_redirects: 0,
+            match_redirects: None,
+            detect_open_redirect: false,
+            migrate_after: None,
+            scope: Vec::new(),
+            shuffle: None,
+            variants: Variants::default(),
+            word_headers: HashMap::new(),
+            total,
+            progress: None,
+        }
+    }
+
+    pub fn show_progress(&mut self) {
+        // stdout only ever carries result lines
+        let progress =
+            ProgressBar::with_draw_target(Some(self.total), ProgressDrawTarget::stderr());
         progress.set_style(
             ProgressStyle::with_template(
-                "[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({percent}%) ETA {eta}",
+                "[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({percent}%) ETA {eta} {msg}",
             )
             .unwrap()
             .progress_chars("##~"),
         );
 
-        let matcher = Matcher::default();
+        self.progress = Some(progress);
+    }
 
-        Ok(Self {
-            reader,
-            client,
-            matcher,
-            progress,
-        })
+    pub fn connection_info(&self) -> client::ConnectionInfo {
+        self.client.connection_info()
     }
 
-    pub fn fuzz(&mut self, base_req: http::Request) -> anyhow::Result<()> {
-        let stdout = std::io::stdout();
-        let mut out = stdout.lock();
+    pub fn fuzz(&mut self, base_req: http::Request) -> anyhow::Result<Vec<FuzzResult>> {
+        let mut results = Vec::new();
+
+        let mut words = Vec::new();
+
+        let brute = match &mut self.source {
+            Source::Wordlist(reader) => {
+                for line in WordlistLines::new(reader.by_ref(), self.strict_encoding) {
+                    words.push(line?.trim().to_string());
+                }
+                None
+            }
+            Source::Brute(brute) => Some(brute.clone()),
+        };
 
-        let mut pending = VecDeque::new();
+        let started = Instant::now();
+        let (total, skip) = match &brute {
+            Some(brute) => {
+                let total = brute.total() as usize;
+                (total, self.restore_checkpoint(brute.hash(), total))
+            }
+            None => (
+                words.len(),
+                self.restore_checkpoint(checkpoint::hash_words(&words), words.len()),
+            ),
+        };
 
-        for line in self.reader.by_ref().lines() {
-            let word = line?.trim().to_string();
-            pending.push_back(word);
+        // words waiting to be sent and sent words waiting for response, with their wordlist index
+        let mut pending: VecDeque<(usize, String)> = words
+            .iter()
+            .enumerate()
+            .flat_map(|(index, word)| {
+                self.variants
+                    .expand(word)
+                    .into_iter()
+                    .map(move |variant| (index, variant))
+            })
+            .collect();
+        // brute-force words are generated as the queue runs low, so memory stays bounded
+        let mut feed: Box<dyn Iterator<Item = (usize, String)>> = match &brute {
+            Some(brute) => Box::new((skip..).zip(brute.words_from(skip as u64))),
+            None => Box::new(std::iter::empty()),
+        };
+        let requests = if brute.is_some() {
+            total
+        } else {
+            pending.len()
+        };
+        if let Some(progress) = &self.progress {
+            progress.set_length(requests as u64);
+        }
+        let done = if brute.is_some() {
+            skip
+        } else {
+            let done = pending.partition_point(|(index, _)| *index < skip);
+            pending.drain(..done);
+            done
+        };
+        if let Some(progress) = &self.progress {
+            progress.inc(done as u64);
+        }
+        if let Some(seed) = self.shuffle {
+            log::info!("shuffling wordlist with seed {seed}");
+            pending
+                .make_contiguous()
+                .shuffle(&mut rand::rngs::StdRng::seed_from_u64(seed));
         }
+        let mut sent: HashMap<u64, (usize, String)> = HashMap::new();
+        // number of retries per queued word
+        let mut retried: HashMap<(usize, String), u32> = HashMap::new();
+        // times a word was sent again after a connection dropped it unprocessed
+        let mut requeued: HashMap<(usize, String), u32> = HashMap::new();
+        // redirects waiting to be followed, with the chain of paths leading to them
+        let mut follow: VecDeque<((usize, String), http::Request, Vec<String>)> = VecDeque::new();
+        // chain and request of sent redirects
+        let mut chains: HashMap<u64, (Vec<String>, http::Request)> = HashMap::new();
+        // server sent GOAWAY and reconnecting is disabled
+        let mut halted = false;
+        // why the circuit breaker stopped the scan
+        let mut aborted: Option<String> = None;
+        let mut progress_logged = Instant::now();
+
+        loop {
+            pending.extend(feed.by_ref().take(FEED_AHEAD.saturating_sub(pending.len())));
+
+            let stopping = self.interrupted.load(Ordering::Relaxed)
+                || halted
+                || aborted.is_some()
+                || self.max_requests_sent();
+            if (stopping || (pending.is_empty() && follow.is_empty()))
+                && !self.client.has_in_flight()
+            {
+                break;
+            }
 
-        while !pending.is_empty() || self.client.has_in_flight() {
             self.client.poll_io()?;
 
-            for resp in self.client.poll_responses()? {
-                if self.matcher.matches(&resp) {
-                    writeln!(out, "[{}] {}", resp.status, resp.path)?;
-                }
-                self.progress.inc(1);
+            let mut retry = Vec::new();
+
+            let mut completed = self.client.poll_responses()?;
+            if let Some(timeout) = self.request_timeout {
+                completed.extend(self.client.reap_timed_out(timeout).into_iter().map(Err));
+            }
+            // e.g. idle timeout, the streams of a closed connection never complete
+            let closed = self.client.is_closed();
+            if closed {
+                completed.extend(self.client.abandon_in_flight().into_iter().map(Err));
             }
 
-            while let Some(word) = pending.front() {
-                let req = base_req.with_path(&word);
+            for completed in completed {
+                let mut resp = match completed {
+                    Ok(resp) => resp,
+                    Err(failure) => {
+                        // a failed redirect is retried from the original word
+                        chains.remove(&failure.stream_id);
+                        match sent.remove(&failure.stream_id) {
+                            // the server never processed it, so it goes out again on the next
+                            // connection. Counted apart from --retries, so a server which keeps
+                            // closing can't loop us
+                            Some(word)
+                                if matches!(
+                                    failure.error,
+                                    StreamError::GoAway | StreamError::ConnectionClosed
+                                ) && self.reconnect
+                                    && requeued.get(&word).copied().unwrap_or(0)
+                                        < RECONNECT_ATTEMPTS =>
+                            {
+                                *requeued.entry(word.clone()).or_default() += 1;
+                                retry.push(word);
+                                continue;
+                            }
+
+                            Some(word)
+                                if failure.error.is_retryable()
+                                    && retried.get(&word).copied().unwrap_or(0) < self.retries =>
+                            {
+                                *retried.entry(word.clone()).or_default() += 1;
+                                log::info!("{}: {}, retrying", failure.path, failure.error);
+                                retry.push(word);
+                                continue;
+                            }
 
-                match self.client.send_request(&req) {
-                    Ok(_) => {
-                        pending.pop_front();
+                            _ => {}
+                        }
+
+                        log::warn!("{}: {}", failure.path, failure.error);
+                        self.summary.errors += 1;
+                        if let Some(progress) = &self.progress {
+                            progress.inc(1);
+                        }
+                        continue;
                     }
+                };
 
-                    Err(ClientError::InFlightFull | ClientError::WouldBlock) => {
-                        break; // backpressure, retry later
+                let word = sent.remove(&resp.stream_id);
+                let (authority, redirected) = match chains.remove(&resp.stream_id) {
+                    Some((chain, req)) => {
+                        resp.redirects = chain;
+                        (req.host.clone(), Some(req))
                     }
+                    None => (base_req.host.clone(), None),
+                };
+                self.summary.record(resp.status);
+                if let Some(progress) = &self.progress {
+                    progress.set_message(self.summary.status_counts());
+                }
+                if let Some(on_response) = self.on_response.as_mut() {
+                    on_response(&resp);
+                }
+                if let Some(throttle) = self.throttle.as_mut() {
+                    throttle.record(resp.status, sent.len());
+                }
+                if let Some(breaker) = self.breaker.as_mut()
+                    && breaker.record(resp.status)
+                    && aborted.is_none()
+                {
+                    log::warn!("{}, stopping scan", breaker.reason());
+                    aborted = Some(breaker.reason());
+                }
+
+                let matched = self.matcher.matches(&resp);
+                // the request this answers, rebuilt from the word at the start of a chain. Only
+                // needed to follow a redirect and for the HAR log
+                let answered = if (300..400).contains(&resp.status) || self.har.is_some() {
+                    Some(match (redirected, &word) {
+                        (Some(req), _) => req,
+                        (None, Some((index, word))) => word_request(
+                            &base_req,
+                            &self.word_headers,
+                            words.get(*index).unwrap_or(word),
+                            word,
+                        ),
+                        (None, None) => base_req.clone(),
+                    })
+                } else {
+                    None
+                };
+                let open_redirect = match &word {
+                    Some((_, word)) if self.detect_open_redirect => {
+                        self.open_redirect(&base_req, &resp, &authority, word)
+                    }
+                    _ => None,
+                };
+                // only the end of a redirect chain is reported
+                if matched
+                    && let Some(word) = &word
+                    && let Some(answered) = &answered
+                    && let Some(req) = self.redirect_target(answered, &resp, &authority)
+                {
+                    resp.discard_body_file();
+                    let mut chain = resp.redirects;
+                    chain.push(resp.path);
+                    follow.push_back((word.clone(), req, chain));
+                    continue;
+                }
+
+                // checked on the end of the chain, the matcher decides which redirects to follow
+                let redirects_match = self
+                    .match_redirects
+                    .as_ref()
+                    .is_none_or(|range| range.contains(&resp.redirect_count()));
+                if open_redirect.is_some() {
+                    self.summary.open_redirects += 1;
+                }
+                if (matched && redirects_match) || open_redirect.is_some() {
+                    self.summary.matched += 1;
+                    let req = answered.as_ref().unwrap_or(&base_req);
+                    self.report(req, &resp, open_redirect.as_ref())?;
+
+                    if self.collect {
+                        results.push(FuzzResult {
+                            word: word.map(|(_, word)| word).unwrap_or_default(),
+                            response: resp,
+                        });
+                    }
+                } else {
+                    // only bodies of reported responses are left in --stream-bodies
+                    resp.discard_body_file();
+                }
+                if let Some(progress) = &self.progress {
+                    progress.inc(1);
+                }
+
+                if self
+                    .summary
+                    .received
+                    .is_multiple_of(checkpoint::CHECKPOINT_INTERVAL)
+                {
+                    self.save_checkpoint(&pending, &sent, &follow, total)?;
+                }
+            }
+
+            if let Some(concurrency) = self.concurrency.as_mut() {
+                concurrency.update(
+                    self.client.rtt(),
+                    self.client.stats().recv_bytes,
+                    self.summary.received + self.summary.errors,
+                );
+            }
+
+            if self.progress.is_none() && progress_logged.elapsed() >= PROGRESS_LOG_INTERVAL {
+                log::info!(
+                    "{}/{requests} requests done ({})",
+                    done as u64 + self.summary.received + self.summary.errors,
+                    self.summary.status_counts()
+                );
+                progress_logged = Instant::now();
+            }
+
+            // requeue in wordlist order, so the front stays the oldest unsent word
+            retry.sort();
+            for word in retry.into_iter().rev() {
+                pending.push_front(word);
+            }
+
+            if closed && !stopping {
+                match self.client.close_reason() {
+                    Some(reason) => log::warn!("connection lost, {reason}"),
+                    None if self.client.is_timed_out() => {
+                        log::warn!("connection closed on idle timeout")
+                    }
+                    None => log::warn!("connection closed"),
+                }
+                if self.reconnect {
+                    self.reconnect_with_backoff()?;
+                } else {
+                    log::warn!("reconnecting is disabled, stopping scan");
+                    halted = true;
+                }
+            } else if self.client.is_draining() && !self.client.has_in_flight() && !stopping {
+                if self.reconnect {
+                    self.client.reconnect()?;
+                } else {
+                    log::warn!("server sent GOAWAY, stopping scan");
+                    halted = true;
+                }
+            }
+
+            // when stopping only drain responses to requests already sent
+            if stopping {
+                continue;
+            }
+
+            // redirects go first, so their words complete sooner
+            while let Some((_, req, _)) = follow.front() {
+                let Some(stream_id) = self.try_send(req, sent.len())? else {
+                    break;
+                };
+                if let Some((word, req, chain)) = follow.pop_front() {
+                    sent.insert(stream_id, word);
+                    chains.insert(stream_id, (chain, req));
+                }
+            }
+
+            while let Some((index, word)) = pending.front() {
+                let entry = words.get(*index).unwrap_or(word);
+                let req = word_request(&base_req, &self.word_headers, entry, word);
+
+                let Some(stream_id) = self.try_send(&req, sent.len())? else {
+                    break;
+                };
+                sent.insert(stream_id, (*index, word.clone()));
+                pending.pop_front();
+            }
+
+            if let Some(metrics) = self.metrics.as_mut() {
+                metrics.update(&self.summary, sent.len());
+            }
+
+            if let Some(after) = self.migrate_after
+                && self.summary.received >= after
+            {
+                self.migrate_after = None; // once per scan
+                if let Err(e) = self.client.migrate() {
+                    log::warn!("failed to migrate the connection: {e:#}");
+                }
+            }
+        }
+
+        self.save_checkpoint(&pending, &sent, &follow, total)?;
+
+        // also after Ctrl-C, in-flight responses have been drained by then
+        if let Err(e) = self.client.close() {
+            log::warn!("failed to close the connection: {e:#}");
+        }
+
+        self.out.flush()?;
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.publish(&self.summary, 0);
+        }
+        if let Some(csv) = self.csv.as_mut() {
+            csv.flush()?;
+        }
+        if let Some(sqlite) = self.sqlite.as_mut() {
+            sqlite.flush()?;
+        }
+        if let Some(har) = self.har.as_mut() {
+            har.finish()?;
+        }
+        if let Some(stats) = self.stats_json.as_mut() {
+            let info = self.client.connection_info();
+            stats.write(&summary::TransportStats {
+                summary: &self.summary,
+                elapsed: started.elapsed(),
+                quic: self.client.stats(),
+                paths: self.client.path_stats(),
+                cc: info.cc,
+                max_ack_delay: info.max_ack_delay,
+                peer_max_ack_delay: info.peer_max_ack_delay,
+            })?;
+        }
+
+        if let Some(progress) = &self.progress {
+            if self.interrupted.load(Ordering::Relaxed) {
+                progress.abandon_with_message("interrupted");
+            } else {
+                progress.finish(); // keeps the final status counts
+            }
+        }
+
+        self.summary.ecn = self.client.ecn_counts();
+        if self.bench {
+            let info = self.client.connection_info();
+            let bench = summary::Bench {
+                summary: &self.summary,
+                elapsed: started.elapsed(),
+                quic: self.client.stats(),
+                cc: info.cc,
+                ack_delays: info.ack_delays(),
+            };
+            eprint!("{bench}");
+        } else if self.show_summary {
+            eprint!("{}", self.summary);
+        }
+        if let Some(reason) = aborted {
+            eprintln!("scan aborted: {reason}");
+        }
+
+        Ok(results)
+    }
+
+    // Replaces a closed connection, giving the server a little longer after each failed attempt
+    fn reconnect_with_backoff(&mut self) -> anyhow::Result<()> {
+        let mut backoff = RECONNECT_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match self.client.reconnect() {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < RECONNECT_ATTEMPTS => {
+                    log::warn!("reconnecting failed: {e:#}, retrying in {backoff:?}");
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.context(format!("failed to reconnect {attempt} times"))),
+            }
+        }
+    }
+
+    fn max_requests_sent(&self) -> bool {
+        self.max_requests
+            .is_some_and(|max| self.summary.sent >= max)
+    }
+
+    // Sends a request, or returns None when it has to wait for in-flight ones to complete
+    fn try_send(
+        &mut self,
+        req: &http::Request,
+        in_flight: usize,
+    ) -> Result<Option<u64>, ClientError> {
+        // checked each loop so responses keep being polled while waiting
+        if let Some(next) = self.next_send
+            && Instant::now() < next
+        {
+            return Ok(None);
+        }
+        if self.max_requests_sent() {
+            return Ok(None);
+        }
+        if let Some(throttle) = &self.throttle
+            && !throttle.allows(in_flight)
+        {
+            return Ok(None);
+        }
+        if let Some(concurrency) = &self.concurrency
+            && !concurrency.allows(in_flight)
+        {
+            return Ok(None);
+        }
+        if let Some(max) = self.max_inflight_bytes
+            && self.client.buffered_bytes() >= max
+        {
+            return Ok(None); // wait for buffered bodies to complete
+        }
 
-                    Err(e) => return Err(e.into()),
+        match self.client.send_request(req) {
+            Ok(stream_id) => {
+                self.summary.sent += 1;
+                if !self.delay.is_zero() || !self.jitter.is_zero() {
+                    let jitter = self.jitter.mul_f64(self.rng.random::<f64>());
+                    self.next_send = Some(Instant::now() + self.delay + jitter);
                 }
+                Ok(Some(stream_id))
             }
+
+            Err(ClientError::InFlightFull | ClientError::WouldBlock | ClientError::Draining) => {
+                Ok(None) // backpressure, retry later
+            }
+
+            Err(e) => Err(e),
+        }
+    }
+
+    // Request following a redirect of `answered`, if it stays in scope and doesn't loop
+    fn redirect_target(
+        &self,
+        answered: &http::Request,
+        resp: &http::Response,
+        authority: &str,
+    ) -> Option<http::Request> {
+        if !(300..400).contains(&resp.status) || resp.redirects.len() >= self.max_redirects {
+            return None;
+        }
+
+        let (current, target) = redirect_url(answered, resp, authority)?;
+        let host = target.host_str()?;
+
+        // the connection only speaks for the host named in its SNI, any other would need its own
+        let same_origin = target.scheme() == current.scheme()
+            && target.port_or_known_default() == current.port_or_known_default()
+            && host.eq_ignore_ascii_case(self.client.server_name());
+        if !same_origin {
+            log::info!(
+                "{}: not following redirect to {target}, out of scope",
+                resp.path
+            );
+            return None;
+        }
+
+        let path = match target.query() {
+            Some(query) => format!("{}?{query}", target.path()),
+            None => target.path().to_string(),
+        };
+
+        if path == resp.path || resp.redirects.contains(&path) {
+            log::debug!("{}: redirect loop to {path}", resp.path);
+            return None;
+        }
+
+        let mut req = answered.clone();
+        req.path = path;
+        // RFC 9110 15.4: a 303, or a 301 or 302 to a POST, is followed with a GET without the
+        // body. Only 307 and 308 keep both
+        if resp.status == 303
+            || (matches!(resp.status, 301 | 302) && req.method.eq_ignore_ascii_case("POST"))
+        {
+            req.method = "GET".to_string();
+            req.body = None;
+            req.form = None;
+            req.headers
+                .retain(|(name, _)| !name.to_ascii_lowercase().starts_with("content-"));
+        }
+        Some(req)
+    }
+
+    // Returns the number of leading words already completed in a previous run
+    fn restore_checkpoint(&mut self, hash: u64, total: usize) -> usize {
+        let Some(checkpoint) = self.checkpoint.as_mut() else {
+            return 0;
+        };
+
+        let skip = match checkpoint.wordlist_hash {
+            Some(prev) if prev != hash => {
+                log::warn!("wordlist changed since last checkpoint, starting from the beginning");
+                0
+            }
+            _ => checkpoint.next.min(total),
+        };
+
+        checkpoint.wordlist_hash = Some(hash);
+        checkpoint.next = skip;
+
+        if skip > 0 {
+            log::info!(
+                "resuming at word {skip}, {} matches found previously",
+                checkpoint.matches.len()
+            );
+        }
+
+        skip
+    }
+
+    fn save_checkpoint(
+        &mut self,
+        pending: &VecDeque<(usize, String)>,
+        sent: &HashMap<u64, (usize, String)>,
+        follow: &VecDeque<((usize, String), http::Request, Vec<String>)>,
+        total: usize,
+    ) -> std::io::Result<()> {
+        let Some(checkpoint) = self.checkpoint.as_mut() else {
+            return Ok(());
+        };
+
+        // everything before the oldest unanswered word is done. Redirects in flight are in
+        // `sent`, the ones still queued keep their word unfinished too
+        checkpoint.next = sent
+            .values()
+            .map(|(index, _)| *index)
+            .chain(follow.iter().map(|((index, _), _, _)| *index))
+            .chain(pending.front().map(|(index, _)| *index))
+            .min()
+            .unwrap_or(total);
+
+        checkpoint.save()
+    }
+
+    // Location of a redirect off the target whose host came from the word sent, e.g. from
+    // `?next=FUZZ` with the word `https://evil.example`. Hosts in scope are trusted
+    fn open_redirect(
+        &self,
+        base_req: &http::Request,
+        resp: &http::Response,
+        authority: &str,
+        word: &str,
+    ) -> Option<url::Url> {
+        if !(300..400).contains(&resp.status) {
+            return None;
+        }
+        let (_, target) = redirect_url(base_req, resp, authority)?;
+        let host = target.host_str()?.to_ascii_lowercase();
+        if host == base_req.host || self.scope.contains(&host) {
+            return None;
+        }
+
+        // the word may have been sent percent-encoded, and url normalizes hosts to lowercase
+        let word = percent_encoding::percent_decode_str(word)
+            .decode_utf8_lossy()
+            .to_ascii_lowercase();
+        word.contains(&host).then_some(target)
+    }
+
+    // Writes a matched response to stdout and all configured outputs
+    // `req` is the request as sent, for the HAR log
+    fn report(
+        &mut self,
+        req: &http::Request,
+        resp: &http::Response,
+        open_redirect: Option<&url::Url>,
+    ) -> anyhow::Result<()> {
+        if let Some(format) = &self.format {
+            // written in one go, so sinks never hold half a record
+            let mut record = Vec::new();
+            writeln!(record, "{}", format.render(resp))?;
+            if let Some(target) = open_redirect {
+                writeln!(record, "    possible open redirect to {target}")?;
+            }
+            self.report_verbose(&mut record, resp)?;
+
+            // the bar is cleared while printing, so it doesn't end up between result lines
+            match &self.progress {
+                Some(progress) => progress.suspend(|| self.out.write_all(&record))?,
+                None => self.out.write_all(&record)?,
+            }
+        }
+        if let Some(csv) = self.csv.as_mut() {
+            csv.write(resp)?;
+        }
+        if let Some(sqlite) = self.sqlite.as_mut() {
+            sqlite.write(resp)?;
+        }
+        if let Some(har) = self.har.as_mut() {
+            har.add(req, resp);
+        }
+        if let Some(bodies) = &self.bodies {
+            bodies.save(resp)?;
+        }
+        if let Some(checkpoint) = self.checkpoint.as_mut() {
+            checkpoint.matches.push((resp.status, resp.path.clone()));
         }
 
-        self.progress.finish_with_message("done fuzzing");
         Ok(())
     }
+
+    fn report_verbose(&self, out: &mut impl Write, resp: &http::Response) -> std::io::Result<()> {
+        if !self.verbose {
+            return Ok(());
+        }
+
+        for line in output::header_lines(&resp.headers) {
+            writeln!(out, "    {line}")?;
+        }
+        for line in output::header_lines(&resp.trailers) {
+            writeln!(out, "    {line} (trailer)")?;
+        }
+        if resp.headers_truncated {
+            writeln!(out, "    (headers truncated)")?;
+        }
+        if let Some(reason) = self.matcher.reason(resp) {
+            writeln!(out, "    {reason}")?;
+        }
+        if !resp.redirects.is_empty() {
+            writeln!(out, "    redirected from {}", resp.redirects.join(" -> "))?;
+        }
+        if resp.truncated {
+            writeln!(
+                out,
+                "    (body truncated to {} bytes)",
+                resp.body_bytes().len()
+            )?;
+        }
+        if let Some(path) = &resp.body_file {
+            writeln!(out, "    body saved to {}", path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+// Extra forms of each wordlist entry to try besides the entry itself
+#[derive(Default)]
+pub struct Variants {
+    pub transforms: Vec<config::Transform>,
+    pub add_slash: bool,       // also try each word with a trailing slash
+    pub mutate: Option<usize>, // derive up to this many mutations of each word
+}
+
+// Appended by --mutate, e.g. for editor backups and leftover copies
+const MUTATE_SUFFIXES: [&str; 6] = ["~", ".bak", ".old", ".orig", ".swp", ".tmp"];
+
+// Characters --mutate inserts or substitutes, separators and digits are what changes most
+// between versions of a name (backup-1, backup_2)
+const MUTATE_CHARS: [char; 13] = [
+    '-', '_', '.', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9',
+];
+
+impl Variants {
+    // Returns the word followed by its variants, without duplicates
+    pub fn expand(&self, word: &str) -> Vec<String> {
+        let mut words = vec![word.to_string()];
+
+        for transform in &self.transforms {
+            let variant = transform.apply(word);
+            if !words.contains(&variant) {
+                words.push(variant);
+            }
+        }
+
+        if self.add_slash {
+            let slashed: Vec<String> = words
+                .iter()
+                .filter(|word| !word.ends_with('/'))
+                .map(|word| format!("{word}/"))
+                .collect();
+            for variant in slashed {
+                if !words.contains(&variant) {
+                    words.push(variant);
+                }
+            }
+        }
+
+        if let Some(max) = self.mutate {
+            let mut added = 0;
+            for variant in mutations(word) {
+                if added == max {
+                    break;
+                }
+                if !words.contains(&variant) {
+                    words.push(variant);
+                    added += 1;
+                }
+            }
+        }
+
+        words
+    }
+}
+
+// Mutations of a word, most likely hits first: suffixes, then single character deletions,
+// substitutions and insertions
+fn mutations(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let edit = |at: usize, remove: bool, insert: Option<char>| -> String {
+        let mut s: String = chars[..at].iter().collect();
+        s.extend(insert);
+        s.extend(&chars[at + usize::from(remove)..]);
+        s
+    };
+
+    let mut out: Vec<String> = MUTATE_SUFFIXES
+        .iter()
+        .map(|suffix| format!("{word}{suffix}"))
+        .collect();
+    if chars.len() > 1 {
+        out.extend((0..chars.len()).map(|i| edit(i, true, None)));
+    }
+    for i in 0..chars.len() {
+        out.extend(MUTATE_CHARS.iter().map(|c| edit(i, true, Some(*c))));
+    }
+    for i in 0..=chars.len() {
+        out.extend(MUTATE_CHARS.iter().map(|c| edit(i, false, Some(*c))));
+    }
+
+    out.retain(|variant| variant != word);
+    out
+}
+
+// Request for a word, with the --headers-file headers of the wordlist entry it came from
+pub fn word_request(
+    base_req: &http::Request,
+    word_headers: &HashMap<String, Vec<(String, String)>>,
+    entry: &str,
+    word: &str,
+) -> http::Request {
+    let mut req = base_req.with_path(word);
+    // looked up by the entry, so all variants of a word share its headers
+    if let Some(headers) = word_headers.get(entry) {
+        for (name, value) in headers {
+            req = req.with_header(name, value);
+        }
+    }
+    req
+}
+
+// Prints the requests a scan would send, without connecting to the target
+pub fn dry_run(
+    base_req: &http::Request,
+    word_headers: &HashMap<String, Vec<(String, String)>>,
+    words: impl Iterator<Item = std::io::Result<String>>,
+    variants: &Variants,
+) -> std::io::Result<()> {
+    let mut out = std::io::stdout().lock();
+
+    for line in words {
+        let entry = line?.trim().to_string();
+        for word in variants.expand(&entry) {
+            write_request(
+                &mut out,
+                &word_request(base_req, word_headers, &entry, &word),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// Writes a request as sent: request line, headers in wire order and the body, whose length
+// stands in for it when it isn't text
+fn write_request(out: &mut impl Write, req: &http::Request) -> std::io::Result<()> {
+    writeln!(out, "{} {}", req.method, req.path)?;
+    for h in req.to_quiche() {
+        writeln!(
+            out,
+            "    {}: {}",
+            String::from_utf8_lossy(h.name()),
+            String::from_utf8_lossy(h.value())
+        )?;
+    }
+
+    let Some(body) = &req.body else {
+        return Ok(());
+    };
+    match std::str::from_utf8(body) {
+        Ok(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => {
+            writeln!(out)?;
+            for line in text.lines() {
+                writeln!(out, "{}", format!("    {line}").trim_end())?;
+            }
+        }
+        _ => writeln!(out, "    ({} byte binary body)", body.len())?,
+    }
+
+    Ok(())
 }
 
+// Decides which responses are reported as matches
+pub trait ResponseMatcher {
+    fn matches(&self, resp: &http::Response) -> bool;
+
+    // What made a matched response match, shown in verbose output
+    fn reason(&self, _resp: &http::Response) -> Option<String> {
+        None
+    }
+}
+
+// Plain predicates can be used as matchers
+impl<F: Fn(&http::Response) -> bool> ResponseMatcher for F {
+    fn matches(&self, resp: &http::Response) -> bool {
+        self(resp)
+    }
+}
+
+// Default matcher filtering on status codes and body size
 pub struct Matcher {
     codes: Vec<std::ops::RangeInclusive<u16>>,
     size: Option<RangeInclusive<usize>>,
+    sizes: Vec<usize>,          // exact sizes to keep, empty keeps any
+    filtered_sizes: Vec<usize>, // exact sizes to drop
+    patterns: Vec<Pattern>,     // bodies have to contain one of them, empty keeps any
+    json: Vec<JsonCondition>,   // all have to hold, non-JSON responses never match them
+    empty: Option<bool>,        // whether the body has to be empty, None accepts both
+    soft_404: Option<Baseline>, // responses looking like this are dropped
+}
+
+// Text searched for in response bodies, a literal substring or a regex written as /.../
+#[derive(Clone)]
+pub struct Pattern {
+    pub source: String, // as given, shown when it matches
+    regex: regex::bytes::Regex,
+}
+
+impl Pattern {
+    pub fn parse(source: &str) -> Result<Self, regex::Error> {
+        let regex = match source.strip_prefix('/').and_then(|s| s.strip_suffix('/')) {
+            Some(regex) => regex::bytes::Regex::new(regex)?,
+            None => regex::bytes::Regex::new(&regex::escape(source))?,
+        };
+
+        Ok(Self {
+            source: source.to_string(),
+            regex,
+        })
+    }
+
+    pub fn is_match(&self, body: &[u8]) -> bool {
+        self.regex.is_match(body)
+    }
+}
+
+// Comparison of a field in a JSON body, e.g. "data.status=ok"
+#[derive(Clone)]
+pub struct JsonCondition {
+    path: Vec<String>,         // object keys or array indexes
+    value: String,             // compared with string fields
+    parsed: serde_json::Value, // compared with other fields, e.g. true, 1 or null
+}
+
+impl JsonCondition {
+    pub fn parse(condition: &str) -> anyhow::Result<Self> {
+        let (path, value) = condition
+            .split_once('=')
+            .filter(|(path, _)| !path.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("invalid JSON condition, expected PATH=VALUE"))?;
+
+        Ok(Self {
+            path: path.split('.').map(str::to_string).collect(),
+            value: value.to_string(),
+            parsed: serde_json::from_str(value)
+                .unwrap_or_else(|_| serde_json::Value::String(value.to_string())),
+        })
+    }
+
+    fn holds(&self, body: &serde_json::Value) -> bool {
+        let field = self.path.iter().try_fold(body, |value, key| match value {
+            serde_json::Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+            value => value.get(key),
+        });
+
+        match field {
+            Some(serde_json::Value::String(s)) => *s == self.value,
+            Some(field) => *field == self.parsed,
+            None => false,
+        }
+    }
+}
+
+fn json_body(resp: &http::Response) -> Option<serde_json::Value> {
+    if resp.truncated || !resp.content_type()?.contains("json") {
+        return None;
+    }
+
+    serde_json::from_slice(&resp.body_bytes()).ok()
+}
+
+// Signature of the response to a path which shouldn't exist
+pub struct Baseline {
+    status: u16,
+    size: usize,
+    body_hash: u64,
+}
+
+impl Baseline {
+    pub fn from_response(resp: &http::Response) -> Self {
+        Self {
+            status: resp.status,
+            size: resp.size(),
+            body_hash: hash_body(&resp.body_bytes()),
+        }
+    }
+
+    fn matches(&self, resp: &http::Response) -> bool {
+        resp.status == self.status
+            && resp.size() == self.size
+            && hash_body(&resp.body_bytes()) == self.body_hash
+    }
 }
 
 impl Matcher {
@@ -102,17 +1149,91 @@ impl Matcher {
         self
     }
 
-    pub fn matches(&self, resp: &http::Response) -> bool {
+    pub fn with_sizes(mut self, sizes: Vec<usize>) -> Self {
+        self.sizes = sizes;
+        self
+    }
+
+    pub fn without_sizes(mut self, sizes: Vec<usize>) -> Self {
+        self.filtered_sizes = sizes;
+        self
+    }
+
+    pub fn with_patterns(mut self, patterns: Vec<Pattern>) -> Self {
+        self.patterns = patterns;
+        self
+    }
+
+    pub fn with_json(mut self, conditions: Vec<JsonCondition>) -> Self {
+        self.json = conditions;
+        self
+    }
+
+    pub fn with_empty(mut self, empty: bool) -> Self {
+        self.empty = Some(empty);
+        self
+    }
+
+    pub fn with_soft_404(mut self, baseline: Baseline) -> Self {
+        self.soft_404 = Some(baseline);
+        self
+    }
+
+    fn matched_pattern(&self, resp: &http::Response) -> Option<&Pattern> {
+        let body = resp.body_bytes();
+        self.patterns.iter().find(|p| p.is_match(&body))
+    }
+}
+
+impl ResponseMatcher for Matcher {
+    fn matches(&self, resp: &http::Response) -> bool {
         if !self.codes.iter().any(|r| r.contains(&resp.status)) {
             return false;
         }
 
+        if let Some(baseline) = &self.soft_404
+            && baseline.matches(resp)
+        {
+            return false;
+        }
+
+        if !self.sizes.is_empty() && !self.sizes.contains(&resp.size()) {
+            return false;
+        }
+        if self.filtered_sizes.contains(&resp.size()) {
+            return false;
+        }
+
+        if let Some(empty) = self.empty
+            && (resp.size() == 0) != empty
+        {
+            return false;
+        }
+
+        if !self.patterns.is_empty() && self.matched_pattern(resp).is_none() {
+            return false;
+        }
+
+        if !self.json.is_empty() {
+            let Some(body) = json_body(resp) else {
+                return false;
+            };
+            if !self.json.iter().all(|c| c.holds(&body)) {
+                return false;
+            }
+        }
+
         if let Some(ref size) = self.size {
-            size.contains(&resp.body.len())
+            size.contains(&resp.size())
         } else {
             true
         }
     }
+
+    fn reason(&self, resp: &http::Response) -> Option<String> {
+        self.matched_pattern(resp)
+            .map(|pattern| format!("matched pattern {}", pattern.source))
+    }
 }
 
 impl Default for Matcher {
@@ -127,12 +1248,321 @@ impl Default for Matcher {
             500..=500,
         ];
 
-        Self { codes, size: None }
+        Self {
+            codes,
+            size: None,
+            sizes: Vec::new(),
+            filtered_sizes: Vec::new(),
+            patterns: Vec::new(),
+            json: Vec::new(),
+            empty: None,
+            soft_404: None,
+        }
+    }
+}
+
+fn hash_body(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+// URL a redirect was sent from and the one its location points to
+fn redirect_url(
+    base_req: &http::Request,
+    resp: &http::Response,
+    authority: &str,
+) -> Option<(url::Url, url::Url)> {
+    let location = resp.headers.get("location")?;
+    let current =
+        url::Url::parse(&format!("{}://{authority}{}", base_req.scheme, resp.path)).ok()?;
+    let target = current.join(location).ok()?;
+    Some((current, target))
+}
+
+// Opens a wordlist, transparently decompressing it when it's gzipped
+fn open_wordlist(path: &str) -> std::io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let gzipped = path.ends_with(".gz") || reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    if gzipped {
+        let decoder = flate2::read::GzDecoder::new(reader);
+        return Ok(Box::new(BufReader::new(decoder)));
+    }
+
+    Ok(Box::new(reader))
+}
+
+// Lines of a wordlist, for reading it without a scan
+pub fn read_wordlist(
+    path: &str,
+    strict_encoding: bool,
+) -> std::io::Result<impl Iterator<Item = std::io::Result<String>>> {
+    Ok(WordlistLines::new(open_wordlist(path)?, strict_encoding))
+}
+
+// Lines of a wordlist. Lines which aren't UTF-8 are decoded as Latin-1, which maps every byte
+// to a character, and counted in a warning once the last line was read. With `strict` they're
+// an error instead
+struct WordlistLines<R> {
+    reader: R,
+    strict: bool,
+    buf: Vec<u8>,
+    line: u64,
+    latin1: u64, // lines decoded as Latin-1
+}
+
+impl<R: BufRead> WordlistLines<R> {
+    fn new(reader: R, strict: bool) -> Self {
+        Self {
+            reader,
+            strict,
+            buf: Vec::new(),
+            line: 0,
+            latin1: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for WordlistLines<R> {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buf.clear();
+        match self.reader.read_until(b'\n', &mut self.buf) {
+            Ok(0) => {
+                if self.latin1 > 0 {
+                    log::warn!(
+                        "{} wordlist lines weren't valid UTF-8 and were read as Latin-1",
+                        self.latin1
+                    );
+                    self.latin1 = 0;
+                }
+                return None;
+            }
+            Ok(_) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        self.line += 1;
+
+        let line = self.buf.strip_suffix(b"\n").unwrap_or(&self.buf);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        match std::str::from_utf8(line) {
+            Ok(line) => Some(Ok(line.to_string())),
+            Err(e) if self.strict => Some(Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("wordlist line {} isn't valid UTF-8: {e}", self.line),
+            ))),
+            Err(_) => {
+                self.latin1 += 1;
+                Some(Ok(line.iter().map(|b| char::from(*b)).collect()))
+            }
+        }
     }
 }
 
 fn count_lines(path: &str) -> std::io::Result<u64> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+    let reader = open_wordlist(path)?;
     Ok(reader.lines().count() as u64)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, TestServer};
+
+    fn rendered(req: &http::Request) -> String {
+        let mut out = Vec::new();
+        write_request(&mut out, req).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn dry_run_renders_word_headers_and_body() {
+        let mut base_req = http::Request::new("https", "example.com", "POST", "/", Vec::new());
+        let form = http::Form::new(
+            vec![http::FormField::Text {
+                name: "q".to_string(),
+                value: "FUZZ".to_string(),
+            }],
+            1,
+        );
+        base_req
+            .headers
+            .push(("content-type".to_string(), form.content_type()));
+        base_req.form = Some(form);
+        let word_headers = HashMap::from([(
+            "admin".to_string(),
+            vec![("X-Role".to_string(), "root".to_string())],
+        )]);
+
+        let out = rendered(&word_request(&base_req, &word_headers, "admin", "admin"));
+        assert!(out.starts_with("POST /admin\n"));
+        assert!(out.contains("    x-role: root\n"));
+        assert!(out.contains("    content-length: 107\n"));
+        assert!(out.contains("\n\n    --fuzzh3-0000000000000001\n"));
+        assert!(out.contains("\n    admin\n"));
+
+        // variants other than the entry's own spelling share its headers
+        let out = rendered(&word_request(&base_req, &word_headers, "admin", "ADMIN"));
+        assert!(out.contains("    x-role: root\n"));
+        let out = rendered(&word_request(&base_req, &word_headers, "guest", "guest"));
+        assert!(!out.contains("x-role"));
+    }
+
+    #[test]
+    fn dry_run_shows_length_of_binary_body() {
+        let mut req = http::Request::new("https", "example.com", "PUT", "/upload", Vec::new());
+        req.body = Some(vec![0, 159, 146, 150]);
+        let out = rendered(&req);
+        assert!(out.ends_with("    content-length: 4\n    (4 byte binary body)\n"));
+    }
+
+    #[test]
+    fn goaway_mid_scan_loses_no_words() {
+        let server = TestServer::start(|_| (200, Vec::new()), Some(8));
+        let words: Vec<String> = (0..30).map(|i| format!("w{i}")).collect();
+
+        let wordlist = test_server::wordlist("goaway", &words);
+        let mut options = crate::FuzzOptions::new(&server.url("/"), &wordlist);
+        options.verify_peer = false;
+        let results = crate::fuzz_with(options).unwrap();
+
+        let mut found: Vec<String> = results.into_iter().map(|r| r.word).collect();
+        found.sort();
+        let mut expected = words.clone();
+        expected.sort();
+        assert_eq!(found, expected);
+        assert_eq!(server.requests().len(), words.len());
+    }
+
+    // GET /r/WORD redirects to /x/WORD, everything else is found
+    fn redirect_route(path: &str) -> (u16, Vec<(&'static str, String)>) {
+        match path.strip_prefix("/r/") {
+            Some(rest) => (302, vec![("location", format!("/x/{rest}"))]),
+            None => (200, Vec::new()),
+        }
+    }
+
+    #[test]
+    fn resume_finishes_a_pending_redirect() {
+        let server = TestServer::start(redirect_route, None);
+        let words = ["r/a", "b", "c"].map(String::from);
+        let wordlist = test_server::wordlist("resume", &words);
+        let state = std::env::temp_dir().join(format!("fuzzh3-resume-{}", std::process::id()));
+        let _ = std::fs::remove_file(&state);
+
+        let scan = |max_requests| {
+            let mut options = crate::FuzzOptions::new(&server.url("/"), &wordlist);
+            options.verify_peer = false;
+            options.max_redirects = 5;
+            options.max_requests = max_requests;
+            let (mut fuzzer, base_req) = crate::build_fuzzer(options).unwrap();
+            let checkpoint = checkpoint::Checkpoint::load_or_new(state.to_str().unwrap());
+            fuzzer.checkpoint = Some(checkpoint.unwrap());
+            fuzzer.fuzz(base_req).unwrap()
+        };
+
+        // stops once /r/a was answered, before its redirect is followed
+        assert!(scan(Some(1)).is_empty());
+        assert_eq!(server.requests(), ["/r/a"]);
+        let saved = std::fs::read_to_string(&state).unwrap();
+        assert!(saved.contains("next 0\n"), "{saved}");
+
+        let results = scan(None);
+        let found: Vec<(&str, &str)> = results
+            .iter()
+            .map(|r| (r.word.as_str(), r.response.path.as_str()))
+            .collect();
+        assert_eq!(found.len(), 3);
+        assert!(found.contains(&("r/a", "/x/a")), "{found:?}");
+        assert!(found.contains(&("b", "/b")));
+        assert!(found.contains(&("c", "/c")));
+        let _ = std::fs::remove_file(&state);
+    }
+
+    #[test]
+    fn redirects_stay_on_the_origin_and_switch_to_get() {
+        let server = TestServer::start(redirect_route, None);
+        let wordlist = test_server::wordlist("redirect-target", &[]);
+        let mut options = crate::FuzzOptions::new(&server.url("/"), &wordlist);
+        options.verify_peer = false;
+        options.max_redirects = 2;
+        let (fuzzer, _) = crate::build_fuzzer(options).unwrap();
+        let authority = format!("127.0.0.1:{}", server.port);
+
+        let headers = vec![("content-type".to_string(), "text/plain".to_string())];
+        let mut post = http::Request::new("https", &authority, "POST", "/a", headers);
+        post.body = Some(b"x".to_vec());
+        let followed = |req: &http::Request, status, location: &str| {
+            let headers = HashMap::from([("location".to_string(), location.to_string())]);
+            let resp = http::Response::new(0, "/a", status, headers, Vec::new(), Duration::ZERO);
+            fuzzer.redirect_target(req, &resp, &authority)
+        };
+
+        let next = followed(&post, 307, "b?q=1").unwrap();
+        assert_eq!(
+            (next.method.as_str(), next.path.as_str()),
+            ("POST", "/b?q=1")
+        );
+        assert_eq!(next.body.as_deref(), Some(&b"x"[..]));
+        assert_eq!(next.headers.len(), 1);
+
+        for status in [301, 302, 303] {
+            let next = followed(&post, status, &server.url("/b")).unwrap();
+            assert_eq!((next.method.as_str(), next.path.as_str()), ("GET", "/b"));
+            assert!(next.body.is_none());
+            assert!(next.headers.is_empty());
+        }
+        let mut put = post.clone();
+        put.method = "PUT".to_string();
+        assert_eq!(followed(&put, 302, "/b").unwrap().method, "PUT");
+        assert_eq!(followed(&put, 303, "/b").unwrap().method, "GET");
+
+        // other hosts, ports and schemes need their own connection
+        let port = server.port + 1;
+        for location in [
+            "https://example.com/b".to_string(),
+            format!("https://127.0.0.1:{port}/b"),
+            format!("http://127.0.0.1:{}/b", server.port),
+        ] {
+            assert!(followed(&post, 302, &location).is_none(), "{location}");
+        }
+        assert!(followed(&post, 200, "/b").is_none());
+        assert!(followed(&post, 302, "/a").is_none());
+    }
+
+    #[test]
+    fn redirects_stop_at_loops_and_the_limit() {
+        let server = TestServer::start(redirect_route, None);
+        let wordlist = test_server::wordlist("redirect-limit", &[]);
+        let mut options = crate::FuzzOptions::new(&server.url("/"), &wordlist);
+        options.verify_peer = false;
+        options.max_redirects = 2;
+        let (fuzzer, base_req) = crate::build_fuzzer(options).unwrap();
+        let authority = format!("127.0.0.1:{}", server.port);
+
+        let headers = HashMap::from([("location".to_string(), "/c".to_string())]);
+        let mut resp = http::Response::new(0, "/b", 302, headers, Vec::new(), Duration::ZERO);
+        resp.redirects = vec!["/a".to_string()];
+        assert!(
+            fuzzer
+                .redirect_target(&base_req, &resp, &authority)
+                .is_some()
+        );
+
+        resp.redirects = vec!["/c".to_string()];
+        assert!(
+            fuzzer
+                .redirect_target(&base_req, &resp, &authority)
+                .is_none()
+        );
+
+        resp.redirects = vec!["/a".to_string(), "/z".to_string()];
+        assert!(
+            fuzzer
+                .redirect_target(&base_req, &resp, &authority)
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::VecDeque;
+
+// Responses the 5xx rate is computed over, the breaker can't trip before it's full
+const WINDOW: usize = 100;
+
+// Circuit breaker which trips when the share of 5xx responses among the last WINDOW ones
+// exceeds the threshold
+pub struct Breaker {
+    threshold: u8,          // percent
+    window: VecDeque<bool>, // whether each recent response was a 5xx
+    errors: usize,          // 5xx responses in the window
+}
+
+impl Breaker {
+    pub fn new(threshold: u8) -> Self {
+        Self {
+            threshold,
+            window: VecDeque::with_capacity(WINDOW),
+            errors: 0,
+        }
+    }
+
+    // Records a response, returns true once the error rate is over the threshold
+    pub fn record(&mut self, status: u16) -> bool {
+        let error = (500..600).contains(&status);
+        if self.window.len() == WINDOW && self.window.pop_front() == Some(true) {
+            self.errors -= 1;
+        }
+        self.window.push_back(error);
+        if error {
+            self.errors += 1;
+        }
+
+        self.window.len() == WINDOW && self.rate() > usize::from(self.threshold)
+    }
+
+    // Percentage of 5xx responses in the window
+    pub fn rate(&self) -> usize {
+        self.errors * 100 / self.window.len().max(1)
+    }
+
+    pub fn reason(&self) -> String {
+        format!(
+            "{}% of the last {} responses were 5xx, over the {}% limit",
+            self.rate(),
+            self.window.len(),
+            self.threshold
+        )
+    }
+}
@@ -0,0 +1,91 @@
+use super::checkpoint;
+
+// Largest space --brute accepts, beyond it a scan wouldn't finish anyway
+pub const MAX_WORDS: u64 = 10_000_000_000;
+
+// Every string of a fixed length over a charset, generated in charset order
+#[derive(Clone)]
+pub struct Brute {
+    charset: Vec<char>,
+    length: u32,
+    total: u64,
+}
+
+impl Brute {
+    // None when the charset is empty or the space holds more than MAX_WORDS strings
+    pub fn new(charset: &str, length: u32) -> Option<Self> {
+        let mut chars: Vec<char> = Vec::new();
+        for c in charset.chars() {
+            if !chars.contains(&c) {
+                chars.push(c);
+            }
+        }
+
+        let total = (chars.len() as u64)
+            .checked_pow(length)
+            .filter(|total| (1..=MAX_WORDS).contains(total))?;
+
+        Some(Self {
+            charset: chars,
+            length,
+            total,
+        })
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // Stands in for the wordlist hash in checkpoints
+    pub fn hash(&self) -> u64 {
+        checkpoint::hash_words(&[self.charset.iter().collect(), self.length.to_string()])
+    }
+
+    // Words from index `start` on, without generating the ones before it
+    pub fn words_from(&self, start: u64) -> Words {
+        let base = self.charset.len() as u64;
+        let mut digits = vec![0; self.length as usize];
+        let mut rest = start;
+        for digit in digits.iter_mut().rev() {
+            *digit = (rest % base) as usize;
+            rest /= base;
+        }
+
+        Words {
+            charset: self.charset.clone(),
+            digits,
+            left: self.total.saturating_sub(start),
+        }
+    }
+}
+
+// Lazy iterator over the words of a Brute
+pub struct Words {
+    charset: Vec<char>,
+    digits: Vec<usize>, // index into charset of each character of the next word
+    left: u64,
+}
+
+impl Iterator for Words {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+
+        let word = self.digits.iter().map(|d| self.charset[*d]).collect();
+
+        // count up like an odometer, the last character turning fastest
+        for digit in self.digits.iter_mut().rev() {
+            *digit += 1;
+            if *digit < self.charset.len() {
+                break;
+            }
+            *digit = 0;
+        }
+
+        Some(word)
+    }
+}
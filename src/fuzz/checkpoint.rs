@@ -0,0 +1,92 @@
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+// Responses received between two checkpoint writes
+pub const CHECKPOINT_INTERVAL: u64 = 100;
+
+// Scan progress persisted to disk, so an interrupted scan can be resumed
+//
+// File format, one entry per line:
+//   hash <wordlist hash in hex>
+//   next <index of the first word not yet completed>
+//   match <status> <path>
+pub struct Checkpoint {
+    path: PathBuf,
+    pub wordlist_hash: Option<u64>,
+    pub next: usize,
+    pub matches: Vec<(u16, String)>,
+}
+
+impl Checkpoint {
+    // Loads state from file, or starts a fresh checkpoint if the file doesn't exist yet
+    pub fn load_or_new(path: &str) -> anyhow::Result<Self> {
+        let mut checkpoint = Self {
+            path: path.into(),
+            wordlist_hash: None,
+            next: 0,
+            matches: Vec::new(),
+        };
+
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(checkpoint),
+            Err(e) => return Err(e.into()),
+        };
+
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let (key, value) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow::anyhow!("invalid checkpoint line: {line}"))?;
+
+            match key {
+                "hash" => checkpoint.wordlist_hash = Some(u64::from_str_radix(value, 16)?),
+                "next" => checkpoint.next = value.parse()?,
+                "match" => {
+                    let (status, path) = value
+                        .split_once(' ')
+                        .ok_or_else(|| anyhow::anyhow!("invalid checkpoint match: {value}"))?;
+                    checkpoint.matches.push((status.parse()?, path.to_string()));
+                }
+                _ => anyhow::bail!("unknown checkpoint key: {key}"),
+            }
+        }
+
+        Ok(checkpoint)
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        // write to a temporary file first so a crash never leaves a half-written checkpoint
+        // appended rather than replacing the extension, which for a "*.tmp" state file would
+        // be the file itself
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".partial");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+
+        if let Some(hash) = self.wordlist_hash {
+            writeln!(file, "hash {hash:016x}")?;
+        }
+        writeln!(file, "next {}", self.next)?;
+        for (status, path) in &self.matches {
+            writeln!(file, "match {status} {path}")?;
+        }
+
+        file.flush()?;
+        drop(file);
+        std::fs::rename(tmp, &self.path)
+    }
+}
+
+// FNV-1a, stable across runs and Rust versions unlike DefaultHasher
+pub fn hash_words(words: &[String]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for word in words {
+        for b in word.bytes().chain(std::iter::once(b'\n')) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    hash
+}
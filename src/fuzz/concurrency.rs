@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+// How often the depth is recomputed, a window short enough to follow the link but long
+// enough to average over many responses
+const ADJUST_INTERVAL: Duration = Duration::from_secs(2);
+const INITIAL_DEPTH: usize = 16;
+const MIN_DEPTH: usize = 4;
+const MAX_DEPTH: usize = 1000;
+// Headroom over the measured bandwidth-delay product, lets the depth grow while the link has
+// spare capacity
+const GAIN: f64 = 2.0;
+
+// In-flight cap tuned to the bandwidth-delay product of the download direction: the measured
+// receive rate times the RTT, in responses. Links with a high RTT need more requests in
+// flight to stay busy, on a low RTT ones extra requests only queue at the server
+pub struct AutoConcurrency {
+    depth: usize,
+    adjusted: Instant,
+    recv_bytes: u64, // connection bytes received at the last adjustment
+    responses: u64,  // responses completed at the last adjustment
+}
+
+impl Default for AutoConcurrency {
+    fn default() -> Self {
+        Self {
+            depth: INITIAL_DEPTH,
+            adjusted: Instant::now(),
+            recv_bytes: 0,
+            responses: 0,
+        }
+    }
+}
+
+impl AutoConcurrency {
+    // Recomputes the depth every ADJUST_INTERVAL from running totals of the connection.
+    // Totals restart on reconnect, which only skips one adjustment
+    pub fn update(&mut self, rtt: Duration, recv_bytes: u64, responses: u64) {
+        let elapsed = self.adjusted.elapsed();
+        if elapsed < ADJUST_INTERVAL {
+            return;
+        }
+
+        let bytes = recv_bytes.saturating_sub(self.recv_bytes);
+        let done = responses.saturating_sub(self.responses);
+        self.adjusted = Instant::now();
+        self.recv_bytes = recv_bytes;
+        self.responses = responses;
+        if done == 0 || bytes == 0 {
+            return;
+        }
+
+        let rate = bytes as f64 / elapsed.as_secs_f64();
+        let per_response = bytes as f64 / done as f64;
+        let bdp = rate * rtt.as_secs_f64() / per_response;
+
+        // grows at most twice per adjustment, so a noisy sample can't flood the server
+        let depth =
+            ((bdp * GAIN).ceil() as usize).clamp(MIN_DEPTH, (self.depth * 2).min(MAX_DEPTH));
+        log::info!(
+            "auto concurrency: {depth} in flight (rtt {rtt:?}, {:.0} responses/s)",
+            done as f64 / elapsed.as_secs_f64()
+        );
+        self.depth = depth;
+    }
+
+    pub fn allows(&self, in_flight: usize) -> bool {
+        in_flight < self.depth
+    }
+}
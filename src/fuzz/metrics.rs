@@ -0,0 +1,181 @@
+use super::summary::Summary;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How often the fuzz loop publishes its counters, scrapes see them at most this late
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long a scraper may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Counters as of the last update
+#[derive(Default)]
+struct Snapshot {
+    sent: u64,
+    received: u64,
+    matched: u64,
+    errors: u64,
+    in_flight: usize,
+    rate: f64, // responses per second since the previous update
+    statuses: BTreeMap<u16, u64>,
+}
+
+// Counters of the running scan, served over HTTP in the Prometheus text format
+pub struct Metrics {
+    snapshot: Arc<Mutex<Snapshot>>,
+    updated: Instant,
+    last_received: u64,
+}
+
+impl Metrics {
+    // Serves the counters at http://addr/metrics from a background thread
+    pub fn serve(addr: SocketAddr) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let snapshot = Arc::new(Mutex::new(Snapshot::default()));
+
+        let shared = snapshot.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| respond(stream, &shared));
+                if let Err(e) = result {
+                    log::debug!("failed to serve metrics: {e}");
+                }
+            }
+        });
+        log::info!("serving metrics on http://{addr}/metrics");
+
+        Ok(Self {
+            snapshot,
+            updated: Instant::now(),
+            last_received: 0,
+        })
+    }
+
+    // Publishes the counters, unless they were published less than UPDATE_INTERVAL ago
+    pub fn update(&mut self, summary: &Summary, in_flight: usize) {
+        if self.updated.elapsed() >= UPDATE_INTERVAL {
+            self.publish(summary, in_flight);
+        }
+    }
+
+    pub fn publish(&mut self, summary: &Summary, in_flight: usize) {
+        let secs = self.updated.elapsed().as_secs_f64();
+        // a new scan starts its summary from zero
+        let received = summary.received.saturating_sub(self.last_received);
+
+        let Ok(mut snapshot) = self.snapshot.lock() else {
+            return;
+        };
+        *snapshot = Snapshot {
+            sent: summary.sent,
+            received: summary.received,
+            matched: summary.matched,
+            errors: summary.errors,
+            in_flight,
+            rate: if secs > 0.0 {
+                received as f64 / secs
+            } else {
+                0.0
+            },
+            statuses: summary.statuses().clone(),
+        };
+
+        self.updated = Instant::now();
+        self.last_received = summary.received;
+    }
+}
+
+// Answers one scrape, any method and path other than GET /metrics gets a 404
+fn respond(mut stream: TcpStream, snapshot: &Mutex<Snapshot>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < 16 * 1024 {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    let request_line = head.split(|b| *b == b'\r').next().unwrap_or_default();
+    let (status, body) = if request_line.starts_with(b"GET /metrics ") {
+        let body = match snapshot.lock() {
+            Ok(snapshot) => render(&snapshot),
+            Err(_) => String::new(),
+        };
+        ("200 OK", body)
+    } else {
+        ("404 Not Found", String::new())
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\ncontent-type: text/plain; version=0.0.4\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn render(snapshot: &Snapshot) -> String {
+    let metrics = [
+        (
+            "requests_sent_total",
+            "counter",
+            "Requests sent",
+            snapshot.sent.to_string(),
+        ),
+        (
+            "responses_received_total",
+            "counter",
+            "Responses received",
+            snapshot.received.to_string(),
+        ),
+        (
+            "matches_total",
+            "counter",
+            "Responses reported as matches",
+            snapshot.matched.to_string(),
+        ),
+        (
+            "errors_total",
+            "counter",
+            "Requests which failed without a usable response",
+            snapshot.errors.to_string(),
+        ),
+        (
+            "in_flight",
+            "gauge",
+            "Requests waiting for their response",
+            snapshot.in_flight.to_string(),
+        ),
+        (
+            "responses_per_second",
+            "gauge",
+            "Responses received per second",
+            snapshot.rate.to_string(),
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(out, "# HELP fuzzh3_{name} {help}");
+        let _ = writeln!(out, "# TYPE fuzzh3_{name} {kind}");
+        let _ = writeln!(out, "fuzzh3_{name} {value}");
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP fuzzh3_responses_total Responses received per status code"
+    );
+    let _ = writeln!(out, "# TYPE fuzzh3_responses_total counter");
+    for (status, count) in &snapshot.statuses {
+        let _ = writeln!(out, "fuzzh3_responses_total{{status=\"{status}\"}} {count}");
+    }
+
+    out
+}
@@ -0,0 +1,640 @@
+use crate::client::http;
+use quiche::h3::NameValue;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+pub const DEFAULT_FORMAT: &str = "[{status}] {path}";
+
+// Header values longer than this are cut in verbose output
+const MAX_HEADER_VALUE_LEN: usize = 80;
+// Titles longer than this are cut in result lines
+const MAX_TITLE_LEN: usize = 60;
+
+const CSV_HEADER: [&str; 6] = ["status", "path", "size", "words", "lines", "content-type"];
+
+// Matches inserted per SQLite transaction, committing each one separately is slow
+const SQLITE_BATCH: usize = 100;
+
+// Longest file name derived from a path by --save-bodies, longer ones are cut and get a hash
+const MAX_BODY_NAME_LEN: usize = 200;
+
+// Result lines, written to stdout and to any extra sinks, e.g. the --output file
+pub struct Tee {
+    sinks: Vec<Box<dyn Write>>,
+}
+
+impl Default for Tee {
+    fn default() -> Self {
+        Self {
+            sinks: vec![Box::new(std::io::stdout())],
+        }
+    }
+}
+
+impl Tee {
+    // Also writes to the file at path, without colors, appending to it or truncating it first
+    pub fn with_file(mut self, path: &str, append: bool) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        self.sinks.push(Box::new(NoColor {
+            out: BufWriter::new(file),
+            escape: false,
+        }));
+
+        Ok(self)
+    }
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for sink in &mut self.sinks {
+            sink.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        for sink in &mut self.sinks {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Drops ANSI escape sequences on their way to a file
+struct NoColor<W> {
+    out: W,
+    escape: bool, // inside a sequence, which may continue in the next write
+}
+
+impl<W: Write> Write for NoColor<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut plain = Vec::with_capacity(buf.len());
+        for &b in buf {
+            match (self.escape, b) {
+                (false, 0x1b) => self.escape = true,
+                (false, _) => plain.push(b),
+                // sequences end with a letter, e.g. ESC [ 3 2 m
+                (true, b) if b.is_ascii_alphabetic() => self.escape = false,
+                (true, _) => {}
+            }
+        }
+
+        self.out.write_all(&plain)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// Writes matched responses as CSV rows, one per match
+pub struct CsvWriter {
+    out: BufWriter<File>,
+}
+
+impl CsvWriter {
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", CSV_HEADER.join(","))?;
+
+        Ok(Self { out })
+    }
+
+    pub fn write(&mut self, resp: &http::Response) -> std::io::Result<()> {
+        let row = [
+            resp.status.to_string(),
+            csv_escape(&resp.path),
+            csv_number(Some(resp.size())),
+            csv_number(resp.words()),
+            csv_number(resp.lines()),
+            csv_escape(resp.content_type().unwrap_or_default()),
+        ];
+
+        writeln!(self.out, "{}", row.join(","))
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// Inserts matched responses into the results table of a SQLite database, rows of earlier scans
+// are kept
+pub struct SqliteWriter {
+    conn: rusqlite::Connection,
+    uncommitted: usize, // rows inserted in the open transaction
+}
+
+impl SqliteWriter {
+    pub fn create(path: &str) -> rusqlite::Result<Self> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS results (
+                id INTEGER PRIMARY KEY,
+                status INTEGER NOT NULL,
+                path TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                words INTEGER,
+                lines INTEGER,
+                content_type TEXT,
+                timestamp TEXT NOT NULL
+            )",
+        )?;
+
+        Ok(Self {
+            conn,
+            uncommitted: 0,
+        })
+    }
+
+    pub fn write(&mut self, resp: &http::Response) -> rusqlite::Result<()> {
+        if self.uncommitted == 0 {
+            self.conn.execute_batch("BEGIN")?;
+        }
+
+        let timestamp = humantime::format_rfc3339_seconds(std::time::SystemTime::now());
+        self.conn
+            .prepare_cached(
+                "INSERT INTO results (status, path, size, words, lines, content_type, timestamp)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?
+            .execute(rusqlite::params![
+                resp.status,
+                resp.path,
+                resp.size() as i64,
+                resp.words().map(|n| n as i64),
+                resp.lines().map(|n| n as i64),
+                resp.content_type(),
+                timestamp.to_string(),
+            ])?;
+
+        self.uncommitted += 1;
+        if self.uncommitted == SQLITE_BATCH {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> rusqlite::Result<()> {
+        if self.uncommitted > 0 {
+            self.conn.execute_batch("COMMIT")?;
+            self.uncommitted = 0;
+        }
+
+        Ok(())
+    }
+}
+
+// Collects matched exchanges and writes them as an HTTP Archive once the scan ends
+pub struct HarWriter {
+    out: BufWriter<File>,
+    entries: Vec<serde_json::Value>,
+}
+
+impl HarWriter {
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+            entries: Vec::new(),
+        })
+    }
+
+    // `req` is the request as sent, after word substitution and redirects
+    pub fn add(&mut self, req: &http::Request, resp: &http::Response) {
+        let started = std::time::SystemTime::now() - resp.time;
+        let url = format!("{}://{}{}", req.scheme, req.host, req.path);
+        let location = resp.headers.get("location").cloned().unwrap_or_default();
+        // as on the wire, with content-length, without the pseudo-headers already in the URL
+        let headers: Vec<(String, String)> = req
+            .to_quiche()
+            .iter()
+            .filter(|h| !h.name().starts_with(b":"))
+            .map(|h| {
+                (
+                    String::from_utf8_lossy(h.name()).to_string(),
+                    String::from_utf8_lossy(h.value()).to_string(),
+                )
+            })
+            .collect();
+        let body_size = req.body.as_ref().map_or(0, Vec::len);
+
+        self.entries.push(serde_json::json!({
+            "startedDateTime": humantime::format_rfc3339_millis(started).to_string(),
+            "time": resp.time.as_secs_f64() * 1000.0,
+            "request": {
+                "method": req.method,
+                "url": url,
+                "httpVersion": "HTTP/3",
+                "headers": har_headers(headers.iter().map(|(k, v)| (k, v))),
+                "queryString": [],
+                "cookies": [],
+                "headersSize": -1,
+                "bodySize": body_size,
+            },
+            "response": {
+                "status": resp.status,
+                "statusText": "",
+                "httpVersion": "HTTP/3",
+                "headers": har_headers(sorted(&resp.headers)),
+                "cookies": [],
+                "content": {
+                    "size": resp.size(),
+                    "mimeType": resp.content_type().unwrap_or_default(),
+                },
+                "redirectURL": location,
+                "headersSize": -1,
+                "bodySize": resp.size(),
+            },
+            "cache": {},
+            "timings": {
+                "send": 0,
+                "wait": resp.time.as_secs_f64() * 1000.0,
+                "receive": 0,
+            },
+        }));
+    }
+
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        let har = serde_json::json!({
+            "log": {
+                "version": "1.2",
+                "creator": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "entries": self.entries,
+            }
+        });
+
+        serde_json::to_writer_pretty(&mut self.out, &har)?;
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
+
+// Writes the stats of a scan as JSON once it ends, see summary::TransportStats
+pub struct StatsWriter {
+    out: BufWriter<File>,
+}
+
+impl StatsWriter {
+    // Creates the file up front, so a bad path fails before the scan rather than after it
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn write(&mut self, stats: &super::summary::TransportStats) -> std::io::Result<()> {
+        serde_json::to_writer_pretty(&mut self.out, &stats.to_json())?;
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
+
+fn har_headers<'a>(
+    headers: impl IntoIterator<Item = (&'a String, &'a String)>,
+) -> Vec<serde_json::Value> {
+    headers
+        .into_iter()
+        .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
+        .collect()
+}
+
+// Metrics which weren't computed are left empty rather than written as zero
+fn csv_number(value: Option<usize>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Template for the result line printed to stdout, e.g. "{status} {size} {path}"
+pub struct Format {
+    tokens: Vec<Token>,
+    color: bool,
+}
+
+enum Token {
+    Literal(String),
+    Status,
+    Path,
+    Size,
+    Words,
+    Lines,
+    Time,
+    Title,
+    Redirects,
+    Header(String),
+}
+
+impl Format {
+    pub fn parse(template: &str) -> anyhow::Result<Self> {
+        let mut tokens = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                tokens.push(Token::Literal(rest[..start].to_string()));
+            }
+
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow::anyhow!("unclosed '{{' in format: {template}"))?;
+            let name = &rest[start + 1..start + end];
+
+            tokens.push(match name {
+                "status" => Token::Status,
+                "path" => Token::Path,
+                "size" => Token::Size,
+                "words" => Token::Words,
+                "lines" => Token::Lines,
+                "time" => Token::Time,
+                "title" => Token::Title,
+                "redirects" => Token::Redirects,
+                _ => match name.strip_prefix("header:") {
+                    Some(header) if !header.is_empty() => {
+                        Token::Header(header.to_ascii_lowercase())
+                    }
+                    _ => anyhow::bail!("unknown format token: {{{name}}}"),
+                },
+            });
+
+            rest = &rest[start + end + 1..];
+        }
+
+        if !rest.is_empty() {
+            tokens.push(Token::Literal(rest.to_string()));
+        }
+
+        Ok(Self {
+            tokens,
+            color: false,
+        })
+    }
+
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn render(&self, resp: &http::Response) -> String {
+        let mut line = String::new();
+
+        for token in &self.tokens {
+            match token {
+                Token::Literal(s) => line.push_str(s),
+                Token::Status if self.color => line.push_str(&colorize_status(resp.status)),
+                Token::Status => line.push_str(&resp.status.to_string()),
+                Token::Path => line.push_str(&resp.path),
+                Token::Size if resp.truncated => {
+                    line.push_str(&format!("{} (truncated)", resp.size()))
+                }
+                Token::Size => line.push_str(&resp.size().to_string()),
+                Token::Words => line.push_str(&metric(resp.words())),
+                Token::Lines => line.push_str(&metric(resp.lines())),
+                Token::Time => line.push_str(&format!("{}ms", resp.time.as_millis())),
+                Token::Title => match resp.title() {
+                    Some(title) => line.push_str(&truncate(&title, MAX_TITLE_LEN)),
+                    None => line.push('-'),
+                },
+                Token::Redirects => line.push_str(&resp.redirect_count().to_string()),
+                Token::Header(name) => {
+                    let value = resp.headers.get(name).or(resp.trailers.get(name));
+                    line.push_str(value.map(String::as_str).unwrap_or("-"))
+                }
+            }
+        }
+
+        line
+    }
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::parse(DEFAULT_FORMAT).expect("default format is valid")
+    }
+}
+
+fn metric(value: Option<usize>) -> String {
+    value
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn colorize_status(status: u16) -> String {
+    let code = match status {
+        200..=299 => "32", // green
+        300..=399 => "36", // cyan
+        400..=499 => "33", // yellow
+        500..=599 => "31", // red
+        _ => return status.to_string(),
+    };
+
+    format!("\x1b[{code}m{status}\x1b[0m")
+}
+
+// Response headers as "name: value" lines, sorted by name for deterministic output
+pub fn header_lines(headers: &HashMap<String, String>) -> Vec<String> {
+    sorted(headers)
+        .into_iter()
+        .map(|(name, value)| {
+            format!(
+                "{}: {}",
+                display_name(name),
+                truncate(value, MAX_HEADER_VALUE_LEN)
+            )
+        })
+        .collect()
+}
+
+// Writes the status line, every header and the body of a response
+pub fn write_response(out: &mut impl Write, resp: &http::Response) -> std::io::Result<()> {
+    writeln!(out, "HTTP/3 {}", resp.status)?;
+    for (name, value) in sorted(&resp.headers) {
+        writeln!(out, "{}: {value}", display_name(name))?;
+    }
+    if resp.headers_truncated {
+        writeln!(out, "(headers truncated)")?;
+    }
+
+    writeln!(out)?;
+    let body = resp.body_bytes();
+    out.write_all(&body)?;
+    if resp.truncated {
+        writeln!(
+            out,
+            "\n(body truncated to {} of {} bytes)",
+            body.len(),
+            resp.size()
+        )?;
+    }
+
+    if !resp.trailers.is_empty() {
+        writeln!(out)?;
+    }
+    for (name, value) in sorted(&resp.trailers) {
+        writeln!(out, "{}: {value}", display_name(name))?;
+    }
+
+    Ok(())
+}
+
+// Response header names arrive lowercase, shown with each dash-separated part capitalized,
+// e.g. "content-type" -> "Content-Type"
+fn display_name(name: &str) -> String {
+    name.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn sorted(headers: &HashMap<String, String>) -> Vec<(&String, &String)> {
+    let mut headers: Vec<_> = headers.iter().collect();
+    headers.sort();
+    headers
+}
+
+fn truncate(value: &str, max: usize) -> String {
+    if value.chars().count() <= max {
+        value.to_string()
+    } else {
+        let mut cut: String = value.chars().take(max).collect();
+        cut.push('…');
+        cut
+    }
+}
+
+// Saves raw bodies of matched responses into a directory
+pub struct BodySaver {
+    dir: PathBuf,
+}
+
+impl BodySaver {
+    pub fn create(dir: &str) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        Ok(Self { dir: dir.into() })
+    }
+
+    pub fn save(&self, resp: &http::Response) -> std::io::Result<PathBuf> {
+        let name = format!("{}_{}", sanitize_path(&resp.path), resp.status);
+
+        let mut file = self.dir.join(&name);
+        let mut n = 1;
+        while file.exists() {
+            file = self.dir.join(format!("{name}_{n}"));
+            n += 1;
+        }
+
+        match &resp.body_file {
+            Some(streamed) => {
+                std::fs::copy(streamed, &file)?;
+            }
+            None => std::fs::write(&file, &resp.body)?,
+        }
+        Ok(file)
+    }
+}
+
+// Turns a request path into a safe file name, e.g. "/admin/login.php" -> "admin_login.php"
+fn sanitize_path(path: &str) -> String {
+    let name: String = path
+        .trim_matches('/')
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+
+    // avoid empty, "." and ".." file names
+    let name = if name.chars().all(|c| c == '.') {
+        format!("index{name}")
+    } else {
+        name
+    };
+
+    // file systems refuse names over 255 bytes, which leaves room for the status and a counter.
+    // The name is ASCII, so any byte is a char boundary
+    if name.len() > MAX_BODY_NAME_LEN {
+        let hash = super::checkpoint::hash_words(std::slice::from_ref(&name));
+        format!("{}_{hash:016x}", &name[..MAX_BODY_NAME_LEN - 17])
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn har_records_the_request_as_sent() {
+        let path = std::env::temp_dir().join(format!("fuzzh3-har-{}.har", std::process::id()));
+        let mut har = HarWriter::create(path.to_str().unwrap()).unwrap();
+
+        let headers = vec![("X-Role".to_string(), "root".to_string())];
+        let mut req = http::Request::new("https", "example.com", "POST", "/login", headers);
+        req.body = Some(b"user=admin".to_vec());
+        let resp =
+            http::Response::new(0, "/login", 200, HashMap::new(), Vec::new(), Duration::ZERO);
+        har.add(&req, &resp);
+
+        let entry = &har.entries[0]["request"];
+        assert_eq!(entry["method"], "POST");
+        assert_eq!(entry["url"], "https://example.com/login");
+        assert_eq!(entry["bodySize"], 10);
+        assert_eq!(
+            entry["headers"],
+            serde_json::json!([
+                { "name": "x-role", "value": "root" },
+                { "name": "content-length", "value": "10" },
+            ])
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn body_file_names_stay_in_the_directory() {
+        assert_eq!(sanitize_path("/admin/login.php"), "admin_login.php");
+        assert_eq!(sanitize_path("/a?b=c&d"), "a_b_c_d");
+        assert_eq!(sanitize_path("/../etc/passwd"), ".._etc_passwd");
+        assert_eq!(sanitize_path("/"), "index");
+        assert_eq!(sanitize_path("/."), "index.");
+        assert_eq!(sanitize_path("/.."), "index..");
+        assert_eq!(sanitize_path("/caf\u{e9}"), "caf_");
+    }
+
+    #[test]
+    fn long_body_file_names_are_cut_and_hashed() {
+        let long = format!("/{}", "a".repeat(300));
+        let name = sanitize_path(&long);
+        assert_eq!(name.len(), MAX_BODY_NAME_LEN);
+        assert!(name.starts_with(&"a".repeat(MAX_BODY_NAME_LEN - 17)));
+
+        // paths sharing the kept prefix still get their own file
+        assert_ne!(name, sanitize_path(&format!("{long}b")));
+    }
+}
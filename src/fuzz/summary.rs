@@ -0,0 +1,193 @@
+use crate::client::EcnCounts;
+use crate::config::CongestionControl;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+// Counters collected during a fuzz run, printed once it finishes
+#[derive(Default)]
+pub struct Summary {
+    pub sent: u64,
+    pub received: u64,
+    pub matched: u64,
+    pub errors: u64,              // streams which completed without a usable response
+    pub open_redirects: u64,      // see --detect-open-redirect
+    pub ecn: Option<EcnCounts>,   // of the last connection, see --ecn
+    classes: [u64; 5],            // responses per status class, 1xx..5xx
+    other: u64,                   // responses with a status outside 100-599
+    statuses: BTreeMap<u16, u64>, // responses per exact status code
+}
+
+impl Summary {
+    pub fn record(&mut self, status: u16) {
+        self.received += 1;
+        *self.statuses.entry(status).or_default() += 1;
+
+        match status {
+            100..=599 => self.classes[(status / 100 - 1) as usize] += 1,
+            _ => self.other += 1,
+        }
+    }
+
+    pub fn statuses(&self) -> &BTreeMap<u16, u64> {
+        &self.statuses
+    }
+
+    // Running counts per status code, e.g. "200:12 403:4 500:1"
+    pub fn status_counts(&self) -> String {
+        let counts: Vec<String> = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{status}:{count}"))
+            .collect();
+
+        counts.join(" ")
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "requests sent:      {}", self.sent)?;
+        writeln!(f, "responses received: {}", self.received)?;
+        writeln!(f, "matches found:      {}", self.matched)?;
+        if self.errors > 0 {
+            writeln!(f, "failed requests:    {}", self.errors)?;
+        }
+        if self.open_redirects > 0 {
+            writeln!(f, "open redirects:     {}", self.open_redirects)?;
+        }
+
+        for (i, count) in self.classes.iter().enumerate() {
+            if *count > 0 {
+                writeln!(f, "  {}xx: {count}", i + 1)?;
+            }
+        }
+        if self.other > 0 {
+            writeln!(f, "  other: {}", self.other)?;
+        }
+        if let Some(ecn) = &self.ecn {
+            writeln!(f, "ECN marks received: {ecn}")?;
+        }
+
+        Ok(())
+    }
+}
+
+// Throughput of a benchmark run
+pub struct Bench<'a> {
+    pub summary: &'a Summary,
+    pub elapsed: Duration,
+    pub quic: quiche::Stats, // of the last connection only
+    pub cc: CongestionControl,
+    pub ack_delays: String, // see client::ConnectionInfo::ack_delays
+}
+
+impl fmt::Display for Bench<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64();
+        let rate = if secs > 0.0 {
+            self.summary.received as f64 / secs
+        } else {
+            0.0
+        };
+
+        writeln!(f, "requests sent:      {}", self.summary.sent)?;
+        writeln!(f, "responses received: {}", self.summary.received)?;
+        writeln!(f, "failed requests:    {}", self.summary.errors)?;
+        writeln!(f, "elapsed:            {secs:.2}s")?;
+        writeln!(f, "requests/sec:       {rate:.1}")?;
+        writeln!(
+            f,
+            "packets sent/recv:  {} / {}",
+            self.quic.sent, self.quic.recv
+        )?;
+        writeln!(
+            f,
+            "packets lost:       {} ({} retransmitted)",
+            self.quic.lost, self.quic.retrans
+        )?;
+        writeln!(
+            f,
+            "bytes sent/recv:    {} / {}",
+            self.quic.sent_bytes, self.quic.recv_bytes
+        )?;
+        writeln!(f, "congestion control: {:?}", self.cc)?;
+        writeln!(f, "max ACK delay:      {}", self.ack_delays)?;
+        if let Some(ecn) = &self.summary.ecn {
+            writeln!(f, "ECN marks received: {ecn}")?;
+        }
+        Ok(())
+    }
+}
+
+// Counters of a run written by --stats-json
+pub struct TransportStats<'a> {
+    pub summary: &'a Summary,
+    pub elapsed: Duration,
+    pub quic: quiche::Stats,           // of the last connection only
+    pub paths: Vec<quiche::PathStats>, // of the last connection only
+    pub cc: CongestionControl,
+    pub max_ack_delay: u64,              // ms, ours as configured
+    pub peer_max_ack_delay: Option<u64>, // ms, None when the server's is unknown
+}
+
+impl TransportStats<'_> {
+    pub fn to_json(&self) -> serde_json::Value {
+        let paths: Vec<serde_json::Value> = self
+            .paths
+            .iter()
+            .map(|path| {
+                serde_json::json!({
+                    "local_addr": path.local_addr.to_string(),
+                    "peer_addr": path.peer_addr.to_string(),
+                    "active": path.active,
+                    "validation_state": format!("{:?}", path.validation_state),
+                    "rtt_ms": millis(path.rtt),
+                    "min_rtt_ms": path.min_rtt.map(millis),
+                    "rttvar_ms": millis(path.rttvar),
+                    "cwnd": path.cwnd,
+                    "pmtu": path.pmtu,
+                    "delivery_rate": path.delivery_rate,
+                    "sent": path.sent,
+                    "recv": path.recv,
+                    "lost": path.lost,
+                    "retrans": path.retrans,
+                    "sent_bytes": path.sent_bytes,
+                    "recv_bytes": path.recv_bytes,
+                    "lost_bytes": path.lost_bytes,
+                    "stream_retrans_bytes": path.stream_retrans_bytes,
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "elapsed_ms": millis(self.elapsed),
+            "requests": {
+                "sent": self.summary.sent,
+                "received": self.summary.received,
+                "matched": self.summary.matched,
+                "errors": self.summary.errors,
+            },
+            "connection": {
+                "congestion_control": format!("{:?}", self.cc),
+                "max_ack_delay_ms": self.max_ack_delay,
+                "peer_max_ack_delay_ms": self.peer_max_ack_delay,
+                "sent": self.quic.sent,
+                "recv": self.quic.recv,
+                "lost": self.quic.lost,
+                "spurious_lost": self.quic.spurious_lost,
+                "retrans": self.quic.retrans,
+                "sent_bytes": self.quic.sent_bytes,
+                "recv_bytes": self.quic.recv_bytes,
+                "acked_bytes": self.quic.acked_bytes,
+                "lost_bytes": self.quic.lost_bytes,
+                "stream_retrans_bytes": self.quic.stream_retrans_bytes,
+            },
+            "paths": paths,
+        })
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,56 @@
+// Throttled responses (429/503) since the last adjustment before the in-flight cap is halved
+const THRESHOLD: u32 = 5;
+// Successful responses since the last adjustment before the in-flight cap is raised by one
+const RECOVERY: u32 = 20;
+
+// Adaptive in-flight cap: halved when the server starts throttling, slowly raised back
+// once it recovers
+#[derive(Default)]
+pub struct Throttle {
+    limit: Option<usize>, // None while not throttling
+    ceiling: usize,       // in-flight count when throttling started
+    throttled: u32,
+    ok: u32,
+}
+
+impl Throttle {
+    pub fn record(&mut self, status: u16, in_flight: usize) {
+        if status == 429 || status == 503 {
+            self.throttled += 1;
+        } else {
+            self.ok += 1;
+        }
+
+        if self.throttled >= THRESHOLD {
+            let current = self.limit.unwrap_or_else(|| {
+                self.ceiling = in_flight.max(1);
+                self.ceiling
+            });
+            let limit = (current / 2).max(1);
+
+            log::warn!("server is throttling, lowering in-flight limit to {limit}");
+            self.limit = Some(limit);
+            self.reset();
+        } else if self.ok >= RECOVERY
+            && let Some(limit) = self.limit
+        {
+            if limit + 1 >= self.ceiling {
+                log::info!("server recovered, removing in-flight limit");
+                self.limit = None;
+            } else {
+                log::info!("raising in-flight limit to {}", limit + 1);
+                self.limit = Some(limit + 1);
+            }
+            self.reset();
+        }
+    }
+
+    pub fn allows(&self, in_flight: usize) -> bool {
+        self.limit.is_none_or(|limit| in_flight < limit)
+    }
+
+    fn reset(&mut self) {
+        self.throttled = 0;
+        self.ok = 0;
+    }
+}
@@ -1,67 +1,1162 @@
+use base64::Engine;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
-use clap::Parser;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
 use client::http;
 use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-mod client;
-mod config;
-mod fuzz;
+pub mod client;
+pub mod config;
+mod error;
+pub mod fuzz;
+#[cfg(test)]
+mod test_server;
+
+pub use error::FuzzError;
+pub use fuzz::FuzzResult;
+
+// Longest wait between two connection attempts, however many failed before
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+// How long single requests made outside of a scan wait for their response
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// --brute spaces above this many words are warned about, they take hours even on a fast link
+const LARGE_BRUTE_SPACE: u64 = 10_000_000;
+
+// Streams of seeded_rng, one per use of randomness
+const RNG_JITTER: u64 = 1;
+const RNG_PROBE: u64 = 2;
+const RNG_FORM: u64 = 3;
+
+// Options for running a scan through the library API
+pub struct FuzzOptions {
+    pub url: String,
+    pub wordlist: String,
+    pub method: config::Method,
+    pub headers: Vec<(String, String)>, // sent in this order
+    pub cookies: Vec<String>,           // merged into a single cookie header
+    pub word_headers: HashMap<String, Vec<(String, String)>>, // extra headers for single words
+    pub basic_auth: Option<(String, String)>, // user and password
+    pub bearer: Option<String>,         // token for an Authorization: Bearer header
+    pub user_agent: Option<String>,     // overridden by a user-agent header
+    pub verify_peer: bool,
+    pub match_codes: Option<Vec<RangeInclusive<u16>>>, // None keeps the default status codes
+    pub match_size: Option<RangeInclusive<usize>>,
+    pub match_sizes: Vec<usize>, // exact body sizes to keep, empty keeps any
+    pub filter_sizes: Vec<usize>, // exact body sizes to drop
+    pub match_patterns: Vec<fuzz::Pattern>, // bodies have to contain one of them, empty keeps any
+    pub match_json: Vec<fuzz::JsonCondition>, // all of them have to hold, non-JSON never matches
+    pub match_empty: Option<bool>, // Some(true) keeps only empty bodies, Some(false) non-empty ones
+    pub detect_404: bool,        // probe a random path and drop responses identical to it
+    pub matcher: Option<Box<dyn fuzz::ResponseMatcher>>, // replaces match_codes and match_size
+    pub on_response: Option<fuzz::ResponseCallback>, // see Fuzzer::on_response
+    pub max_body_size: Option<usize>,
+    pub max_headers: Option<usize>, // header fields kept per response
+    pub stream_bodies: Option<PathBuf>, // bodies are written here instead of kept in memory
+    pub max_inflight_bytes: Option<usize>, // body bytes buffered across all in-flight streams
+    pub request_timeout: Option<Duration>,
+    pub retries: u32,
+    pub connect_retries: u32,
+    pub connect_backoff: Duration, // wait before the first connection retry, doubled after each
+    pub max_requests: Option<u64>,
+    pub reconnect: bool,
+    pub adaptive: bool,
+    pub auto_concurrency: bool,
+    pub abort_on_errors: Option<u8>,
+    pub delay: Duration,      // minimum pause between two sends
+    pub jitter: Duration,     // random extra pause added to delay
+    pub max_redirects: usize, // 0 disables following redirects
+    pub match_redirects: Option<RangeInclusive<usize>>, // redirect chain lengths reported
+    pub detect_open_redirect: bool,
+    pub scope: Vec<String>, // extra hosts redirects may lead to without being open redirects
+    pub idle_timeout: Duration,
+    pub max_data: u64,
+    pub max_streams: u64,
+    pub qpack_max_table: Option<u64>,
+    pub qpack_blocked: Option<u64>,
+    pub max_ack_delay: u64, // ms
+    pub allow_migration: bool,
+    pub migrate_after: Option<u64>, // responses received before moving to a new local port
+    pub ecn: bool,
+    pub quic_version: u32,
+    pub cc: config::CongestionControl,
+    pub mtu: usize,
+    pub proxy: Option<String>, // URL of a MASQUE CONNECT-UDP proxy
+    pub discover: bool,        // take the HTTP/3 port from the server's Alt-Svc header
+    pub bind: Option<SocketAddr>,
+    pub session_dir: Option<PathBuf>, // session ticket cache, see config::Args::session_dir
+    pub shuffle: Option<u64>,         // seed for randomizing word order
+    pub seed: Option<u64>,            // seeds every other random choice, None draws fresh ones
+    pub datagram: bool,               // offer QUIC datagrams, see probe_datagram
+    pub transforms: Vec<config::Transform>,
+    pub path_encoding: http::PathEncoding,
+    pub add_slash: bool,
+    pub mutate: Option<usize>,
+    pub strict_encoding: bool, // wordlist lines which aren't UTF-8 abort instead of read as Latin-1
+    pub brute: Option<fuzz::brute::Brute>, // generates the words instead of the wordlist
+    pub fuzz_query: bool,      // replace FUZZ in the URL's query string instead of the path
+    pub request_template: Option<http::RequestTemplate>, // replaces method, path and headers
+    pub form: Option<http::Form>, // multipart body sent with each request
+    pub force_scheme: bool,    // send a :scheme other than https
+}
+
+impl FuzzOptions {
+    pub fn new(url: &str, wordlist: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            wordlist: wordlist.to_string(),
+            method: config::Method::Get,
+            headers: Vec::new(),
+            word_headers: HashMap::new(),
+            cookies: Vec::new(),
+            basic_auth: None,
+            bearer: None,
+            user_agent: Some(config::DEFAULT_USER_AGENT.to_string()),
+            verify_peer: true,
+            match_codes: None,
+            match_size: None,
+            match_sizes: Vec::new(),
+            filter_sizes: Vec::new(),
+            match_patterns: Vec::new(),
+            match_json: Vec::new(),
+            match_empty: None,
+            detect_404: false,
+            matcher: None,
+            on_response: None,
+            max_body_size: None,
+            max_headers: None,
+            stream_bodies: None,
+            max_inflight_bytes: None,
+            request_timeout: None,
+            retries: 0,
+            connect_retries: 0,
+            connect_backoff: Duration::from_millis(500),
+            max_requests: None,
+            reconnect: true,
+            adaptive: false,
+            auto_concurrency: false,
+            abort_on_errors: None,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            max_redirects: 0,
+            match_redirects: None,
+            detect_open_redirect: false,
+            scope: Vec::new(),
+            idle_timeout: config::DEFAULT_IDLE_TIMEOUT,
+            max_data: config::DEFAULT_MAX_DATA,
+            max_streams: config::DEFAULT_MAX_STREAMS,
+            qpack_max_table: None,
+            qpack_blocked: None,
+            max_ack_delay: config::DEFAULT_MAX_ACK_DELAY,
+            allow_migration: false,
+            migrate_after: None,
+            ecn: false,
+            quic_version: quiche::PROTOCOL_VERSION,
+            cc: config::CongestionControl::default(),
+            mtu: config::MAX_DATAGRAM_SIZE,
+            proxy: None,
+            discover: false,
+            bind: None,
+            session_dir: None,
+            shuffle: None,
+            seed: None,
+            datagram: false,
+            transforms: Vec::new(),
+            path_encoding: http::PathEncoding::default(),
+            add_slash: false,
+            mutate: None,
+            strict_encoding: false,
+            brute: None,
+            fuzz_query: false,
+            request_template: None,
+            form: None,
+            force_scheme: false,
+        }
+    }
+
+    fn variants(&self) -> fuzz::Variants {
+        fuzz::Variants {
+            transforms: self.transforms.clone(),
+            add_slash: self.add_slash,
+            mutate: self.mutate,
+        }
+    }
+
+    // Same settings for another target, without matcher and on_response which can't be
+    // copied. Used to scan several targets with what the command line set once
+    fn for_url(&self, url: String) -> Self {
+        Self {
+            url,
+            wordlist: self.wordlist.clone(),
+            method: self.method.clone(),
+            headers: self.headers.clone(),
+            word_headers: self.word_headers.clone(),
+            cookies: self.cookies.clone(),
+            basic_auth: self.basic_auth.clone(),
+            bearer: self.bearer.clone(),
+            user_agent: self.user_agent.clone(),
+            verify_peer: self.verify_peer,
+            match_codes: self.match_codes.clone(),
+            match_size: self.match_size.clone(),
+            match_sizes: self.match_sizes.clone(),
+            filter_sizes: self.filter_sizes.clone(),
+            match_patterns: self.match_patterns.clone(),
+            match_json: self.match_json.clone(),
+            match_empty: self.match_empty,
+            detect_404: self.detect_404,
+            matcher: None,
+            on_response: None,
+            max_body_size: self.max_body_size,
+            max_headers: self.max_headers,
+            stream_bodies: self.stream_bodies.clone(),
+            max_inflight_bytes: self.max_inflight_bytes,
+            request_timeout: self.request_timeout,
+            retries: self.retries,
+            connect_retries: self.connect_retries,
+            connect_backoff: self.connect_backoff,
+            max_requests: self.max_requests,
+            reconnect: self.reconnect,
+            adaptive: self.adaptive,
+            auto_concurrency: self.auto_concurrency,
+            abort_on_errors: self.abort_on_errors,
+            delay: self.delay,
+            jitter: self.jitter,
+            max_redirects: self.max_redirects,
+            match_redirects: self.match_redirects.clone(),
+            detect_open_redirect: self.detect_open_redirect,
+            scope: self.scope.clone(),
+            idle_timeout: self.idle_timeout,
+            max_data: self.max_data,
+            max_streams: self.max_streams,
+            qpack_max_table: self.qpack_max_table,
+            qpack_blocked: self.qpack_blocked,
+            max_ack_delay: self.max_ack_delay,
+            allow_migration: self.allow_migration,
+            migrate_after: self.migrate_after,
+            ecn: self.ecn,
+            quic_version: self.quic_version,
+            cc: self.cc,
+            mtu: self.mtu,
+            proxy: self.proxy.clone(),
+            discover: self.discover,
+            bind: self.bind,
+            session_dir: self.session_dir.clone(),
+            shuffle: self.shuffle,
+            seed: self.seed,
+            datagram: self.datagram,
+            transforms: self.transforms.clone(),
+            path_encoding: self.path_encoding,
+            add_slash: self.add_slash,
+            mutate: self.mutate,
+            strict_encoding: self.strict_encoding,
+            brute: self.brute.clone(),
+            fuzz_query: self.fuzz_query,
+            request_template: self.request_template.clone(),
+            form: self.form.clone(),
+            force_scheme: self.force_scheme,
+        }
+    }
+
+    fn from_args(args: &config::Args) -> anyhow::Result<Self> {
+        let mut options = Self::new(
+            args.url.as_deref().unwrap_or_default(),
+            args.wordlist.as_deref().unwrap_or_default(),
+        );
+
+        options.method = args.method.clone();
+        options.headers = parse_headers(&args.headers)?;
+        if let Some(path) = &args.headers_file {
+            options.word_headers = parse_headers_file(path)?;
+        }
+        options.cookies = args.cookies.clone();
+        if let Some(user) = &args.user {
+            options.basic_auth = Some(parse_credentials(user)?);
+        }
+        options.bearer = args.bearer.clone();
+        options.user_agent = Some(args.user_agent.clone());
+        options.verify_peer = !args.no_verify;
+        if let Some(match_codes) = &args.match_codes {
+            options.match_codes = Some(parse_code_ranges(match_codes)?);
+        }
+        if let Some(match_size) = &args.match_size {
+            options.match_size = Some(parse_size_range(match_size)?);
+        }
+        options.match_sizes = args.match_size_list.clone();
+        options.filter_sizes = args.filter_size_list.clone();
+        if let Some(path) = &args.match_grep_file {
+            options.match_patterns = read_patterns(path)?;
+        }
+        options.match_json = args
+            .match_json
+            .iter()
+            .map(|c| fuzz::JsonCondition::parse(c))
+            .collect::<anyhow::Result<_>>()?;
+        if args.match_empty || args.match_nonempty {
+            options.match_empty = Some(args.match_empty);
+        }
+        options.detect_404 = args.detect_404;
+        options.max_body_size = args.max_body_size;
+        options.max_headers = args.max_headers;
+        if let Some(dir) = &args.stream_bodies {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| anyhow::anyhow!("failed to create {}: {e}", dir.display()))?;
+            options.stream_bodies = Some(dir.clone());
+        }
+        options.max_inflight_bytes = args.max_inflight_bytes;
+        options.request_timeout = args.request_timeout.map(Duration::from_secs);
+        options.retries = args.retries;
+        options.connect_retries = args.connect_retries;
+        options.connect_backoff = Duration::from_millis(args.connect_backoff);
+        options.max_requests = args.max_requests;
+        options.proxy = args.proxy.clone();
+        options.discover = args.discover;
+        options.bind = args.bind;
+        options.session_dir = args.session_dir.clone();
+        options.reconnect = !args.no_reconnect;
+        options.adaptive = args.adaptive;
+        options.auto_concurrency = args.auto_concurrency;
+        options.abort_on_errors = args.abort_on_errors;
+        options.delay = Duration::from_millis(args.delay);
+        options.jitter = Duration::from_millis(args.jitter);
+        options.detect_open_redirect = args.detect_open_redirect;
+        if let Some(range) = &args.match_redirects {
+            options.match_redirects = Some(parse_size_range(range)?);
+        }
+        if args.follow_redirects {
+            options.max_redirects = args.max_redirects;
+        }
+        options.scope = args.scope.clone();
+        options.idle_timeout = Duration::from_secs(args.idle_timeout);
+        options.max_data = args.max_data;
+        options.max_streams = args.max_streams;
+        options.qpack_max_table = args.qpack_max_table;
+        options.qpack_blocked = args.qpack_blocked;
+        options.max_ack_delay = args.max_ack_delay;
+        options.allow_migration = args.allow_migration;
+        options.migrate_after = args.migrate_after;
+        options.ecn = args.ecn;
+        if args.qpack_max_table.is_some_and(|n| n > 0) {
+            // quiche's QPACK decoder doesn't implement the dynamic table yet
+            log::warn!(
+                "quiche can't decode QPACK dynamic table references yet, responses using them will fail"
+            );
+        }
+        if let Some(version) = &args.quic_version {
+            options.quic_version = parse_quic_version(version)?;
+        }
+        options.cc = args.cc;
+        options.mtu = args.mtu.into();
+        options.transforms = args.transform.clone();
+        options.add_slash = args.add_slash;
+        options.mutate = args.mutate.then_some(args.mutate_max);
+        options.strict_encoding = args.strict_encoding;
+        if let (Some(charset), Some(length)) = (&args.brute, args.length) {
+            options.brute = Some(parse_brute(charset, length)?);
+        }
+        options.fuzz_query = args.fuzz_query;
+        if !args.form.is_empty() {
+            let mut rng = seeded_rng(args.seed, RNG_FORM);
+            options.form = Some(parse_form(&args.form, &mut rng)?);
+            // forms are posted unless another method was asked for
+            if matches!(options.method, config::Method::Get) {
+                options.method = config::Method::Post;
+            }
+        }
+        if let Some(path) = &args.request_file {
+            options.request_template = Some(read_request_file(path)?);
+        }
+        options.force_scheme = args.force_scheme;
+        if args.double_encode {
+            options.path_encoding = http::PathEncoding::Double;
+        } else if args.urlencode {
+            options.path_encoding = http::PathEncoding::Percent;
+        }
+        options.seed = args.seed;
+        if args.shuffle {
+            options.shuffle = Some(args.seed.unwrap_or_else(rand::random));
+        }
+
+        Ok(options)
+    }
+}
+
+// Runs a scan without printing anything and returns the matched responses
+pub fn fuzz_with(options: FuzzOptions) -> Result<Vec<FuzzResult>, FuzzError> {
+    let (mut fuzzer, base_req) = build_fuzzer(options)?;
+
+    fuzzer.fuzz(base_req).map_err(FuzzError::Scan)
+}
 
 pub fn run() -> anyhow::Result<()> {
-    let args = config::Args::parse();
+    let mut args = config::Args::load()?;
 
-    // Parse URL and create QUIC config
-    let url = url::Url::parse(&args.url)?;
-    let config = config::QuicConfig::new(&url, args.no_verify)?;
+    // logs go to stderr, stdout is kept for results. Set up after parsing arguments so they
+    // follow --color
+    let style = if args.color().enabled(&std::io::stderr()) {
+        env_logger::WriteStyle::Always
+    } else {
+        env_logger::WriteStyle::Never
+    };
+    env_logger::Builder::from_default_env()
+        .target(env_logger::Target::Stderr)
+        .write_style(style)
+        .init();
 
-    // Initialize QUIC client
-    let client = client::Client::new(config)?;
+    if args.silent {
+        log::set_max_level(log::LevelFilter::Off);
+    }
 
-    // Prepare base HTTP request
-    let base_req = build_base_request(&url, args.method, &args.headers)?;
+    // deleted when the run ends
+    let _downloaded = download_wordlist(&mut args)?;
 
-    // Create and run fuzzer
-    let mut fuzzer = fuzz::Fuzzer::new(client, &args.wordlist)?;
-    if let Some(match_codes) = args.match_codes {
-        fuzzer.matcher = fuzzer.matcher.with_codes(parse_code_ranges(&match_codes)?);
+    let options = FuzzOptions::from_args(&args)?;
+
+    if args.dry_run {
+        let url = url::Url::parse(&options.url)?;
+        let base_req = build_base_request(&url, &options)?;
+        match &options.brute {
+            Some(brute) => fuzz::dry_run(
+                &base_req,
+                &options.word_headers,
+                brute.words_from(0).map(Ok),
+                &options.variants(),
+            )?,
+            None => fuzz::dry_run(
+                &base_req,
+                &options.word_headers,
+                fuzz::read_wordlist(&options.wordlist, options.strict_encoding)?,
+                &options.variants(),
+            )?,
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = &args.replay {
+        return replay(&options, path);
     }
-    if let Some(match_size) = args.match_size {
-        fuzzer.matcher = fuzzer.matcher.with_size(parse_size_range(&match_size)?);
+
+    if args.check {
+        return check(&options);
+    }
+
+    if args.datagram {
+        return probe_datagram(options);
     }
 
-    fuzzer.fuzz(base_req)?;
+    if let Some(other) = &args.diff {
+        return diff(&args, other);
+    }
+
+    // Validate output format before connecting
+    let template = match (args.silent, args.show_title) {
+        (true, _) => "{path}".to_string(),
+        (false, true) => format!("{} {{title}}", args.format),
+        (false, false) => args.format.clone(),
+    };
+    let template = template.as_str();
+    fuzz::output::Format::parse(template)?;
+    let color = args.color().enabled(&std::io::stdout());
+
+    // One scan per --ports entry or --target-file line, each labeled with a prefix for its
+    // result lines and a name for messages
+    let targets = if let Some(path) = &args.target_file {
+        read_targets(path)?
+            .into_iter()
+            .map(|url| (Some((url.clone(), url.clone())), options.for_url(url)))
+            .collect()
+    } else if !args.ports.is_empty() {
+        let url = url::Url::parse(&options.url)?;
+        args.ports
+            .iter()
+            .map(|&port| {
+                let mut url = url.clone();
+                url.set_port(Some(port))
+                    .map_err(|_| FuzzError::InvalidUrl(format!("can't set port on {url}")))?;
+                let label = (port.to_string(), format!("port {port}"));
+                Ok((Some(label), options.for_url(url.to_string())))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    } else {
+        vec![(None, options)]
+    };
+
+    // First Ctrl-C stops sending and drains in-flight requests, second one exits immediately
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_flag = interrupted.clone();
+    ctrlc::set_handler(move || {
+        if handler_flag.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        log::info!("interrupted, waiting for in-flight responses");
+    })?;
+
+    // Shared by the scans of all ports
+    let mut csv = args
+        .output_csv
+        .as_deref()
+        .map(fuzz::output::CsvWriter::create)
+        .transpose()?;
+    let mut out = fuzz::output::Tee::default();
+    if let Some(path) = &args.output {
+        out = out
+            .with_file(path, args.append)
+            .map_err(|e| anyhow::anyhow!("failed to open {path}: {e}"))?;
+    }
+    let mut sqlite = args
+        .output_sqlite
+        .as_deref()
+        .map(|path| {
+            fuzz::output::SqliteWriter::create(path)
+                .map_err(|e| anyhow::anyhow!("failed to open database {path}: {e}"))
+        })
+        .transpose()?;
+    let mut metrics = args
+        .metrics_addr
+        .map(|addr| {
+            fuzz::metrics::Metrics::serve(addr)
+                .map_err(|e| anyhow::anyhow!("failed to serve metrics on {addr}: {e}"))
+        })
+        .transpose()?;
+    let mut bodies = args
+        .save_bodies
+        .as_deref()
+        .map(fuzz::output::BodySaver::create)
+        .transpose()?;
+
+    for (label, options) in targets {
+        if interrupted.load(Ordering::Relaxed) {
+            break;
+        }
+
+        // results are labeled with the target they were found on
+        let format = match &label {
+            Some((prefix, _)) => fuzz::output::Format::parse(&format!("[{prefix}] {template}"))?,
+            None => fuzz::output::Format::parse(template)?,
+        };
+
+        let (mut fuzzer, base_req) = match (build_fuzzer(options), &label) {
+            (Ok(built), _) => built,
+            // an unreachable target doesn't stop the others
+            (Err(e), Some((_, name))) => {
+                eprintln!("{name}: {:#}", anyhow::Error::from(e));
+                continue;
+            }
+            (Err(e), None) => return Err(e.into()),
+        };
+        if let Some((_, name)) = &label
+            && !args.silent
+        {
+            eprintln!("scanning {name}");
+        }
+
+        if args.bench {
+            fuzzer.matcher = Box::new(|_: &http::Response| false);
+            fuzzer.bench = true;
+        }
+        if args.info {
+            eprint!("{}", fuzzer.connection_info());
+        }
+
+        // Matches are printed as they arrive rather than collected
+        fuzzer.format = Some(format.with_color(color));
+        fuzzer.collect = false;
+        fuzzer.verbose = args.verbose && !args.silent;
+        if !args.silent {
+            if !args.no_progress {
+                fuzzer.show_progress();
+            }
+            fuzzer.show_summary = true;
+        }
+        fuzzer.out = std::mem::take(&mut out);
+        fuzzer.csv = csv.take();
+        fuzzer.sqlite = sqlite.take();
+        fuzzer.metrics = metrics.take();
+        fuzzer.bodies = bodies.take();
+        if let Some(path) = &args.output_har {
+            fuzzer.har = Some(fuzz::output::HarWriter::create(path)?);
+        }
+        if let Some(path) = &args.stats_json {
+            fuzzer.stats_json = Some(fuzz::output::StatsWriter::create(path)?);
+        }
+        if let Some(path) = &args.resume {
+            fuzzer.checkpoint = Some(fuzz::checkpoint::Checkpoint::load_or_new(path)?);
+        }
+        fuzzer.interrupted = interrupted.clone();
+
+        fuzzer.fuzz(base_req)?;
+
+        out = std::mem::take(&mut fuzzer.out);
+        csv = fuzzer.csv.take();
+        sqlite = fuzzer.sqlite.take();
+        metrics = fuzzer.metrics.take();
+        bodies = fuzzer.bodies.take();
+    }
+
+    Ok(())
+}
+
+// Reads a --target-file, skipping empty lines and lines starting with '#'
+fn read_targets(path: &str) -> anyhow::Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read target file {path}: {e}"))?;
+
+    let targets: Vec<String> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    if targets.is_empty() {
+        anyhow::bail!("no targets in {path}");
+    }
+
+    Ok(targets)
+}
+
+// Reads a --match-grep-file, one pattern per non-empty line
+fn read_patterns(path: &str) -> anyhow::Result<Vec<fuzz::Pattern>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read pattern file {path}: {e}"))?;
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| {
+            fuzz::Pattern::parse(line.trim())
+                .map_err(|e| anyhow::anyhow!("{path}:{}: invalid pattern: {e}", n + 1))
+        })
+        .collect()
+}
+
+// Wordlist downloaded to a temporary file, removed on drop
+struct Downloaded(PathBuf);
+
+impl Drop for Downloaded {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// Downloads a --wordlist given as an http(s) URL and points --wordlist at the local copy, so
+// the wordlist is read like any other file
+fn download_wordlist(args: &mut config::Args) -> anyhow::Result<Option<Downloaded>> {
+    let Some(url) = args
+        .wordlist
+        .as_deref()
+        .and_then(|wordlist| url::Url::parse(wordlist).ok())
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+    else {
+        return Ok(None);
+    };
+
+    let path = std::env::temp_dir().join(format!("fuzzh3-wordlist-{}", std::process::id()));
+    // create_new doesn't follow a link planted in the shared directory
+    let file = std::fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| anyhow::anyhow!("failed to create {}: {e}", path.display()))?;
+    let downloaded = Downloaded(path);
+
+    log::info!("downloading wordlist {url}");
+    let mut out = std::io::BufWriter::new(file);
+    let len = client::download::download(&url, !args.no_verify, &mut out)
+        .and_then(|len| Ok(out.flush().map(|()| len)?))
+        .map_err(|e| anyhow::anyhow!("failed to download wordlist {url}: {e:#}"))?;
+    log::info!("downloaded {len} bytes to {}", downloaded.0.display());
+
+    args.wordlist = Some(downloaded.0.to_string_lossy().into_owned());
+    Ok(Some(downloaded))
+}
+
+// Sends a single request and prints the complete response
+fn replay(options: &FuzzOptions, path: &str) -> anyhow::Result<()> {
+    let (mut client, base_req) = connect(options)?;
+
+    let mut req = base_req;
+    req.path = format!("/{}", path.trim_start_matches('/'));
+    if let Some(form) = &req.form {
+        req.body = Some(form.encode(path));
+    }
+
+    let resp = client.fetch(&req, options.request_timeout.unwrap_or(PROBE_TIMEOUT))?;
+    client.close()?;
+    fuzz::output::write_response(&mut std::io::stdout().lock(), &resp)?;
+
+    Ok(())
+}
+
+// Connects and requests / once, failing when the handshake or the request does
+fn check(options: &FuzzOptions) -> anyhow::Result<()> {
+    let (mut client, mut req) = connect(options)?;
+    req.path = "/".to_string();
+
+    let resp = client.fetch(&req, options.request_timeout.unwrap_or(PROBE_TIMEOUT))?;
+    let info = client.connection_info();
+    client.close()?;
+    resp.discard_body_file();
+    println!(
+        "{}: status {}, {} over QUIC version {:#x}{}",
+        options.url,
+        resp.status,
+        info.alpn,
+        info.version,
+        info.negotiation()
+    );
+
+    Ok(())
+}
+
+// Establishes the connection, retrying up to --connect-retries times with a doubling wait.
+// Failures a retry can't fix, a local address in use or a server refusing h3, aren't retried
+fn connect_with_backoff(
+    config: config::QuicConfig,
+    options: &FuzzOptions,
+) -> anyhow::Result<client::Client> {
+    let mut backoff = options.connect_backoff;
+    let mut attempt = 0;
+    loop {
+        let e = match client::Client::new(config.clone()) {
+            Ok(client) => return Ok(client),
+            Err(e) => e,
+        };
+        let permanent = e.is::<client::BindError>()
+            || matches!(
+                e.downcast_ref::<client::HandshakeError>(),
+                Some(client::HandshakeError::AlpnRejected)
+            );
+        if permanent || attempt == options.connect_retries {
+            if attempt > 0 {
+                log::error!(
+                    "giving up on {} after {} connection attempts",
+                    config.remote_addr,
+                    attempt + 1
+                );
+            }
+            return Err(e);
+        }
+
+        attempt += 1;
+        log::warn!(
+            "connecting to {} failed: {e:#}, retry {attempt}/{} in {backoff:?}",
+            config.remote_addr,
+            options.connect_retries
+        );
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+    }
+}
+
+// Sends a datagram and prints what came of it, failing when the server doesn't take datagrams
+fn probe_datagram(mut options: FuzzOptions) -> anyhow::Result<()> {
+    options.datagram = true;
+    let (mut client, _) = connect(&options)?;
+
+    let probe = client.probe_datagram(options.request_timeout.unwrap_or(PROBE_TIMEOUT))?;
+    client.close()?;
+    println!("{}: {probe}", options.url);
+
+    if probe.max_len.is_none() {
+        anyhow::bail!("server doesn't support QUIC datagrams");
+    }
+    Ok(())
+}
+
+// Scans the wordlist against the URL and `other_url`, then prints the paths whose status or
+// size differ. Paths are compared as requested, so both URLs should put FUZZ at the same path
+fn diff(args: &config::Args, other_url: &str) -> anyhow::Result<()> {
+    let mut other = FuzzOptions::from_args(args)?;
+    other.url = other_url.to_string();
+    let targets = [FuzzOptions::from_args(args)?, other];
+
+    let mut scans = Vec::new();
+    for mut options in targets {
+        if !args.silent {
+            eprintln!("scanning {}", options.url);
+        }
+
+        // every response is recorded, none is reported on its own
+        let seen = Rc::new(RefCell::new(HashMap::new()));
+        let record = seen.clone();
+        options.on_response = Some(Box::new(move |resp: &http::Response| {
+            record
+                .borrow_mut()
+                .insert(resp.path.clone(), (resp.status, resp.size()));
+        }));
+        options.matcher = Some(Box::new(|_: &http::Response| false));
+
+        let (mut fuzzer, base_req) = build_fuzzer(options)?;
+        fuzzer.collect = false;
+        if !args.silent && !args.no_progress {
+            fuzzer.show_progress();
+        }
+        fuzzer.fuzz(base_req)?;
+        drop(fuzzer);
+
+        scans.push(seen.take());
+    }
+
+    // a path missing on one side failed there
+    let (a, b) = (&scans[0], &scans[1]);
+    let mut paths: Vec<&String> = a
+        .keys()
+        .chain(b.keys().filter(|p| !a.contains_key(*p)))
+        .collect();
+    paths.sort();
+
+    let side = |resp: Option<&(u16, usize)>| match resp {
+        Some((status, size)) => format!("[{status}] {size}"),
+        None => "-".to_string(),
+    };
+    let mut out = std::io::stdout().lock();
+    for path in paths {
+        let (left, right) = (a.get(path), b.get(path));
+        if left != right {
+            writeln!(out, "{path} {} | {}", side(left), side(right))?;
+        }
+    }
 
     Ok(())
 }
 
-fn build_base_request(
-    url: &url::Url,
-    method: config::Method,
-    headers: &[String],
-) -> anyhow::Result<http::Request> {
-    let headers_map = parse_headers(headers)?;
-    let method_str = method_to_str(method)?;
-    let path = url.path();
+fn build_fuzzer(options: FuzzOptions) -> Result<(fuzz::Fuzzer, http::Request), FuzzError> {
+    let (mut client, base_req) = connect(&options)?;
+
+    // Only the default matcher drops soft-404s
+    let baseline = if options.detect_404 && options.matcher.is_none() {
+        let mut rng = seeded_rng(options.seed, RNG_PROBE);
+        Some(probe_soft_404(&mut client, &base_req, &mut rng)?)
+    } else {
+        None
+    };
+
+    // Create fuzzer
+    let mut fuzzer = match &options.brute {
+        Some(brute) => fuzz::Fuzzer::with_brute(client, brute.clone()),
+        None => {
+            fuzz::Fuzzer::new(client, &options.wordlist).map_err(|source| FuzzError::Wordlist {
+                path: options.wordlist.clone(),
+                source,
+            })?
+        }
+    };
+    fuzzer.variants = options.variants();
+    fuzzer.on_response = options.on_response;
+    fuzzer.reconnect = options.reconnect;
+    fuzzer.request_timeout = options.request_timeout;
+    fuzzer.retries = options.retries;
+    fuzzer.max_requests = options.max_requests;
+    fuzzer.max_inflight_bytes = options.max_inflight_bytes;
+    fuzzer.max_redirects = options.max_redirects;
+    fuzzer.match_redirects = options.match_redirects;
+    fuzzer.detect_open_redirect = options.detect_open_redirect;
+    fuzzer.migrate_after = options.migrate_after;
+    fuzzer.scope = options.scope;
+    fuzzer.shuffle = options.shuffle;
+    fuzzer.word_headers = options.word_headers;
+    fuzzer.strict_encoding = options.strict_encoding;
+    fuzzer.delay = options.delay;
+    fuzzer.jitter = options.jitter;
+    fuzzer.rng = seeded_rng(options.seed, RNG_JITTER);
+    if options.adaptive {
+        fuzzer.throttle = Some(fuzz::throttle::Throttle::default());
+    }
+    if options.auto_concurrency {
+        fuzzer.concurrency = Some(fuzz::concurrency::AutoConcurrency::default());
+    }
+    fuzzer.breaker = options.abort_on_errors.map(fuzz::breaker::Breaker::new);
+    fuzzer.matcher = match options.matcher {
+        Some(matcher) => matcher,
+        None => {
+            let mut matcher = fuzz::Matcher::default();
+            if let Some(match_codes) = options.match_codes {
+                matcher = matcher.with_codes(match_codes);
+            }
+            if let Some(match_size) = options.match_size {
+                matcher = matcher.with_size(match_size);
+            }
+            if !options.match_sizes.is_empty() {
+                matcher = matcher.with_sizes(options.match_sizes);
+            }
+            if !options.filter_sizes.is_empty() {
+                matcher = matcher.without_sizes(options.filter_sizes);
+            }
+            if !options.match_patterns.is_empty() {
+                matcher = matcher.with_patterns(options.match_patterns);
+            }
+            if !options.match_json.is_empty() {
+                matcher = matcher.with_json(options.match_json);
+            }
+            if let Some(empty) = options.match_empty {
+                matcher = matcher.with_empty(empty);
+            }
+            if let Some(baseline) = baseline {
+                matcher = matcher.with_soft_404(baseline);
+            }
+            Box::new(matcher)
+        }
+    };
+
+    Ok((fuzzer, base_req))
+}
+
+// Establishes the HTTP/3 connection and prepares the request words are sent with
+fn connect(options: &FuzzOptions) -> Result<(client::Client, http::Request), FuzzError> {
+    // Parse URL and create QUIC config
+    let url = url::Url::parse(&options.url).map_err(|e| FuzzError::InvalidUrl(format!("{e}")))?;
+
+    // Prepare base HTTP request, before connecting so a bad URL fails fast
+    let base_req = build_base_request(&url, options)?;
+
+    let mut config = match &options.proxy {
+        Some(proxy) => config::QuicConfig::via_proxy(&url, !options.verify_peer, proxy)?,
+        None => config::QuicConfig::new(&url, !options.verify_peer)?,
+    };
+    config.max_body_size = options.max_body_size;
+    config.max_headers = options.max_headers;
+    config.stream_bodies = options.stream_bodies.clone();
+    config.datagram = options.datagram;
+    config.idle_timeout = options.idle_timeout;
+    config.max_data = options.max_data;
+    config.max_streams = options.max_streams;
+    config.qpack_max_table = options.qpack_max_table;
+    config.qpack_blocked = options.qpack_blocked;
+    config.max_ack_delay = options.max_ack_delay;
+    config.allow_migration = options.allow_migration;
+    config.ecn = options.ecn;
+    config.quic_version = options.quic_version;
+    config.cc = options.cc;
+    config.mtu = options.mtu;
+    if options.discover {
+        discover_port(&mut config, &url, &base_req.host);
+    }
+
+    // targets are resolved to IPv4 only, and so is the proxy
+    if let Some(addr) = options.bind.filter(|addr| !addr.is_ipv4()) {
+        return Err(FuzzError::Bind {
+            addr,
+            source: std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "address family doesn't match the target {}",
+                    config.remote_addr.ip()
+                ),
+            ),
+        });
+    }
+    config.bind = options.bind;
+    if let (Some(dir), Some(host), Some(port)) = (
+        &options.session_dir,
+        url.host_str(),
+        url.port_or_known_default(),
+    ) {
+        config.session_file = Some(dir.join(format!("{host}_{port}")));
+    }
+
+    // Initialize QUIC client and HTTP/3 on top of it
+    let host = config.server_name.clone();
+    let mut client = connect_with_backoff(config, options).map_err(|e| match e
+        .downcast::<client::BindError>()
+    {
+        Ok(client::BindError { addr, source }) => FuzzError::Bind { addr, source },
+        Err(e) => match e.downcast::<client::HandshakeError>() {
+            Ok(source) if source.is_missing_h3() => FuzzError::NoHttp3 { host, source },
+            Ok(source) => FuzzError::Handshake(source.into()),
+            Err(e) => FuzzError::Handshake(e),
+        },
+    })?;
+    client.ensure_h3().map_err(FuzzError::H3)?;
+
+    Ok((client, base_req))
+}
+
+// Moves the connection to the port the server advertises HTTP/3 on over TCP, keeping the URL's
+// port when it advertises none or can't be reached
+fn discover_port(config: &mut config::QuicConfig, url: &url::Url, authority: &str) {
+    let addr = SocketAddr::from((
+        *config.remote_addr.ip(),
+        url.port_or_known_default().unwrap_or(443),
+    ));
+    let fallback = config.remote_addr.port();
+
+    match client::altsvc::discover_h3_port(&config.server_name, authority, addr, config.verify_peer)
+    {
+        Ok(Some(port)) => {
+            log::info!("{addr} advertises HTTP/3 on port {port}");
+            config.remote_addr.set_port(port);
+        }
+        Ok(None) => log::warn!("{addr} doesn't advertise HTTP/3, using port {fallback}"),
+        Err(e) => log::warn!("Alt-Svc discovery on {addr} failed: {e:#}, using port {fallback}"),
+    }
+}
+
+// Generator for one use of randomness, repeatable with --seed. Each use gets its own stream,
+// so drawing more in one doesn't change what the others draw
+fn seeded_rng(seed: Option<u64>, stream: u64) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed ^ stream),
+        None => StdRng::from_os_rng(),
+    }
+}
+
+// Requests a random path to learn what the server returns for pages which don't exist
+fn probe_soft_404(
+    client: &mut client::Client,
+    base_req: &http::Request,
+    rng: &mut StdRng,
+) -> Result<fuzz::Baseline, FuzzError> {
+    let req = base_req.with_path(&format!("{:016x}", rng.random::<u64>()));
+    let resp = client.fetch(&req, PROBE_TIMEOUT).map_err(FuzzError::Scan)?;
+    log::info!(
+        "soft-404 baseline: status {}, size {}",
+        resp.status,
+        resp.size()
+    );
+
+    let baseline = fuzz::Baseline::from_response(&resp);
+    resp.discard_body_file();
+    Ok(baseline)
+}
+
+fn build_base_request(url: &url::Url, options: &FuzzOptions) -> Result<http::Request, FuzzError> {
+    // HTTP/3 only carries https, an http:// URL would also default to port 80
+    if url.scheme() != "https" && !options.force_scheme {
+        let mut suggested = url.clone();
+        let hint = match suggested.set_scheme("https") {
+            Ok(()) => format!(", try {suggested}"),
+            Err(()) => String::new(),
+        };
+        return Err(FuzzError::InvalidUrl(format!(
+            "HTTP/3 requires https but the URL uses {}{hint} (--force-scheme sends it anyway)",
+            url.scheme()
+        )));
+    }
+
+    let template = options.request_template.as_ref();
+    let method_str = match template {
+        Some(template) => &template.method,
+        None => method_to_str(options.method.clone()),
+    };
+    let path = template.map_or(url.path(), |template| &template.path);
+
+    let mut headers_map = options.headers.clone();
+    // the file's headers win over -H, Host sets :authority instead
+    let mut authority = None;
+    for (name, value) in template.iter().flat_map(|template| &template.headers) {
+        if name.eq_ignore_ascii_case("host") {
+            authority = Some(value.as_str());
+        } else if let Some(header) = headers_map
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        {
+            *header = (name.clone(), value.clone());
+        } else {
+            headers_map.push((name.clone(), value.clone()));
+        }
+    }
+    if !options.cookies.is_empty() {
+        add_cookies(&mut headers_map, &options.cookies);
+    }
+    if let Some((user, pass)) = &options.basic_auth {
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!("{user}:{pass}"));
+        set_default_header(
+            &mut headers_map,
+            "authorization",
+            &format!("Basic {credentials}"),
+        );
+    }
+    if let Some(token) = &options.bearer {
+        set_default_header(
+            &mut headers_map,
+            "authorization",
+            &format!("Bearer {token}"),
+        );
+    }
+    if let Some(user_agent) = &options.user_agent {
+        set_default_header(&mut headers_map, "user-agent", user_agent);
+    }
 
     let host = url
         .host_str()
-        .ok_or_else(|| anyhow::anyhow!("URL missing host"))?;
+        .ok_or_else(|| FuzzError::InvalidUrl("URL missing host".to_string()))?;
+    let host = authority.unwrap_or(host);
+
+    let mut req = http::Request::new(url.scheme(), host, method_str, path, headers_map);
+    req.encoding = options.path_encoding;
+    req.fuzz_query = options.fuzz_query;
+    // the boundary in the content type has to match the body
+    if let Some(form) = &options.form {
+        req = req.with_header("content-type", &form.content_type());
+        req.form = Some(form.clone());
+    }
+    // the file's path replaces the URL's, query included
+    if template.is_some() {
+        req.template = true;
+    } else {
+        req.query = url.query().map(str::to_string);
+    }
+
+    if options.fuzz_query && !url.query().is_some_and(|q| q.contains(http::FUZZ_KEYWORD)) {
+        return Err(FuzzError::InvalidUrl(format!(
+            "the query must contain {} to fuzz it, e.g. /search?q={}",
+            http::FUZZ_KEYWORD,
+            http::FUZZ_KEYWORD
+        )));
+    }
+
+    Ok(req)
+}
+
+// Sets a header unless the user already set one with the same name
+fn set_default_header(headers: &mut Vec<(String, String)>, name: &str, value: &str) {
+    if !headers.iter().any(|(k, _)| k.eq_ignore_ascii_case(name)) {
+        headers.push((name.to_string(), value.to_string()));
+    }
+}
+
+// Appends cookies to an existing cookie header, so there is only ever one
+fn add_cookies(headers: &mut Vec<(String, String)>, cookies: &[String]) {
+    let index = headers
+        .iter()
+        .position(|(k, _)| k.eq_ignore_ascii_case("cookie"))
+        .unwrap_or_else(|| {
+            headers.push(("cookie".to_string(), String::new()));
+            headers.len() - 1
+        });
 
-    http::Request::new(url.scheme(), host, method_str, path, headers_map)
+    let existing = &mut headers[index].1;
+    let mut values: Vec<&str> = Vec::new();
+    if !existing.is_empty() {
+        values.push(existing);
+    }
+    values.extend(cookies.iter().map(|c| c.trim().trim_end_matches(';')));
+
+    *existing = values.join("; ");
 }
 
-fn method_to_str(method: config::Method) -> anyhow::Result<&'static str> {
-    Ok(match method {
+fn method_to_str(method: config::Method) -> &'static str {
+    match method {
         config::Method::Get => "GET",
         config::Method::Put => "PUT",
         config::Method::Post => "POST",
         config::Method::Delete => "DELETE",
-    })
+    }
 }
 
-fn parse_headers(headers: &[String]) -> anyhow::Result<HashMap<String, String>> {
+fn parse_headers(headers: &[String]) -> anyhow::Result<Vec<(String, String)>> {
     headers
         .iter()
         .map(|h| {
@@ -74,6 +1169,228 @@ fn parse_headers(headers: &[String]) -> anyhow::Result<HashMap<String, String>>
         .collect()
 }
 
+// Reads a --headers-file. Each line is a word followed by its headers, all comma separated:
+//
+//     admin,Authorization: Bearer abc,X-Role: admin
+//     "a,b","Accept: text/html, */*"
+//
+// Fields containing commas are quoted, with "" for a literal quote. Empty lines and
+// lines starting with '#' are skipped.
+fn parse_headers_file(path: &str) -> anyhow::Result<HashMap<String, Vec<(String, String)>>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read headers file {path}: {e}"))?;
+    let mut word_headers = HashMap::new();
+
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let malformed = |reason: &str| anyhow::anyhow!("{path}:{}: {reason}", n + 1);
+        let fields = split_csv_line(line).ok_or_else(|| malformed("unclosed quote"))?;
+        let (word, headers) = fields
+            .split_first()
+            .filter(|(_, headers)| !headers.is_empty())
+            .ok_or_else(|| malformed("expected a word followed by at least one header"))?;
+
+        let headers = headers
+            .iter()
+            .map(|h| {
+                let (k, v) = h
+                    .split_once(':')
+                    .filter(|(k, _)| !k.trim().is_empty())
+                    .ok_or_else(|| malformed(&format!("invalid header format: {h}")))?;
+
+                Ok((k.trim().to_string(), v.trim().to_string()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        word_headers.insert(word.trim().to_string(), headers);
+    }
+
+    Ok(word_headers)
+}
+
+// Reads a request template: a request line with the method and path, then one header per
+// line, e.g.
+//
+//   POST /api/FUZZ?debug=1 HTTP/3
+//   Content-Type: application/json
+//   X-Api-Key: FUZZ
+//
+// The protocol after the path is optional and ignored. Reading stops at the first empty line.
+fn read_request_file(path: &str) -> anyhow::Result<http::RequestTemplate> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read request file {path}: {e}"))?;
+    let mut lines = content.lines().enumerate();
+    let malformed = |n: usize, reason: &str| anyhow::anyhow!("{path}:{}: {reason}", n + 1);
+
+    let (n, request_line) = lines
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("{path}: empty request file"))?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(malformed(
+            n,
+            "expected a request line like GET /FUZZ HTTP/3",
+        ));
+    };
+    if !target.starts_with('/') {
+        return Err(malformed(n, "the path must start with /"));
+    }
+
+    let mut headers = Vec::new();
+    for (n, line) in lines {
+        if line.trim().is_empty() {
+            if content.lines().skip(n + 1).any(|l| !l.trim().is_empty()) {
+                log::warn!(
+                    "{path}: ignoring request body, only the method, path and headers are sent"
+                );
+            }
+            break;
+        }
+        if line.starts_with(':') {
+            return Err(malformed(
+                n,
+                "pseudo-headers come from the URL, use Host to set :authority",
+            ));
+        }
+
+        let (k, v) = line
+            .split_once(':')
+            .filter(|(k, _)| !k.trim().is_empty())
+            .ok_or_else(|| malformed(n, &format!("invalid header format: {line}")))?;
+        headers.push((k.trim().to_string(), v.trim().to_string()));
+    }
+
+    let template = http::RequestTemplate {
+        method: method.to_ascii_uppercase(),
+        path: target.to_string(),
+        headers,
+    };
+    if !template.path.contains(http::FUZZ_KEYWORD)
+        && !template
+            .headers
+            .iter()
+            .any(|(_, v)| v.contains(http::FUZZ_KEYWORD))
+    {
+        anyhow::bail!(
+            "{path}: no {} in the path or a header value",
+            http::FUZZ_KEYWORD
+        );
+    }
+
+    Ok(template)
+}
+
+// Parses --quic-version. quiche only speaks version 1, any other version it can offer is a
+// reserved one which servers answer with version negotiation (RFC 9000 section 15)
+fn parse_quic_version(version: &str) -> anyhow::Result<u32> {
+    let digits = version.trim_start_matches("0x");
+    let version = u32::from_str_radix(digits, 16)
+        .map_err(|e| anyhow::anyhow!("invalid QUIC version {version}: {e}"))?;
+
+    if version != quiche::PROTOCOL_VERSION && version & 0x0f0f_0f0f != 0x0a0a_0a0a {
+        anyhow::bail!(
+            "unsupported QUIC version {version:#x}, expected 0x1 or a reserved version (0x?a?a?a?a)"
+        );
+    }
+    Ok(version)
+}
+
+// Parses --form fields, NAME=@PATH reads the file to upload
+fn parse_form(fields: &[String], rng: &mut StdRng) -> anyhow::Result<http::Form> {
+    let fields = fields
+        .iter()
+        .map(|field| {
+            let (name, value) = field
+                .split_once('=')
+                .filter(|(name, _)| !name.is_empty())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("invalid form field, expected NAME=VALUE: {field}")
+                })?;
+
+            let Some(path) = value.strip_prefix('@') else {
+                return Ok(http::FormField::Text {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                });
+            };
+            let data = std::fs::read(path)
+                .map_err(|e| anyhow::anyhow!("failed to read form file {path}: {e}"))?;
+            let filename = std::path::Path::new(path).file_name().map_or_else(
+                || path.to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            );
+
+            Ok(http::FormField::File {
+                name: name.to_string(),
+                filename,
+                data: Arc::new(data),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(http::Form::new(fields, rng.random()))
+}
+
+// Splits a CSV line into fields, None when a quote isn't closed
+fn split_csv_line(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+
+    if quoted {
+        return None;
+    }
+    fields.push(field);
+
+    Some(fields)
+}
+
+fn parse_brute(charset: &str, length: u32) -> anyhow::Result<fuzz::brute::Brute> {
+    if charset.is_empty() {
+        anyhow::bail!("--brute charset is empty");
+    }
+    let brute = fuzz::brute::Brute::new(charset, length).ok_or_else(|| {
+        anyhow::anyhow!(
+            "--brute space is larger than {} words, use a shorter --length or a smaller charset",
+            fuzz::brute::MAX_WORDS
+        )
+    })?;
+
+    if brute.total() > LARGE_BRUTE_SPACE {
+        log::warn!(
+            "--brute generates {} words, the scan will take long",
+            brute.total()
+        );
+    }
+
+    Ok(brute)
+}
+
+fn parse_credentials(value: &str) -> anyhow::Result<(String, String)> {
+    let (user, pass) = value
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("invalid credentials, expected USER:PASS"))?;
+
+    Ok((user.to_string(), pass.to_string()))
+}
+
 fn parse_code_ranges(values: &[String]) -> anyhow::Result<Vec<RangeInclusive<u16>>> {
     let mut ranges = Vec::new();
 
@@ -92,7 +1409,132 @@ fn parse_code_ranges(values: &[String]) -> anyhow::Result<Vec<RangeInclusive<u16
 fn parse_size_range(value: &String) -> anyhow::Result<RangeInclusive<usize>> {
     let (start, end) = value
         .split_once('-')
-        .ok_or_else(|| anyhow::anyhow!("invalid size range: {value}"))?;
+        .ok_or_else(|| anyhow::anyhow!("invalid range, expected MIN-MAX: {value}"))?;
 
     Ok(start.parse()?..=end.parse()?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_share_the_parsed_options() {
+        let mut options = FuzzOptions::new("https://a.test/FUZZ", "words.txt");
+        options.word_headers = HashMap::from([(
+            "admin".to_string(),
+            vec![("x-role".to_string(), "root".to_string())],
+        )]);
+        options.match_patterns = vec![fuzz::Pattern::parse("/secret/").unwrap()];
+        options.retries = 3;
+
+        let target = options.for_url("https://b.test/FUZZ".to_string());
+        assert_eq!(target.url, "https://b.test/FUZZ");
+        assert_eq!(target.wordlist, "words.txt");
+        assert_eq!(target.word_headers, options.word_headers);
+        assert_eq!(target.match_patterns[0].source, "/secret/");
+        assert_eq!(target.retries, 3);
+    }
+
+    // Writes `content` to a temp file unique to the test and returns its path
+    fn temp_file(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("fuzzh3-{name}-{}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn csv_fields_may_be_quoted() {
+        assert_eq!(split_csv_line("a,b,,c").unwrap(), ["a", "b", "", "c"]);
+        assert_eq!(
+            split_csv_line(r#""a,b","say ""hi""""#).unwrap(),
+            ["a,b", "say \"hi\""]
+        );
+        assert_eq!(split_csv_line(r#""a"b,c"#).unwrap(), ["ab", "c"]);
+        assert!(split_csv_line(r#"a,"b,c"#).is_none());
+    }
+
+    #[test]
+    fn headers_file_maps_words_to_headers() {
+        let path = temp_file(
+            "headers-file",
+            "# comment\n\n\
+             admin,Authorization: Bearer abc, X-Role : admin\n\
+             \"a,b\",\"Accept: text/html, */*\"\n",
+        );
+        let word_headers = parse_headers_file(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let pair = |k: &str, v: &str| (k.to_string(), v.to_string());
+        assert_eq!(word_headers.len(), 2);
+        assert_eq!(
+            word_headers["admin"],
+            [pair("Authorization", "Bearer abc"), pair("X-Role", "admin")]
+        );
+        assert_eq!(word_headers["a,b"], [pair("Accept", "text/html, */*")]);
+    }
+
+    #[test]
+    fn headers_file_errors_name_the_line() {
+        for (content, reason) in [
+            ("admin,\"X-A: 1\n", "2: unclosed quote"),
+            (
+                "admin\n",
+                "2: expected a word followed by at least one header",
+            ),
+            ("admin,no colon\n", "2: invalid header format: no colon"),
+            ("admin,: empty name\n", "2: invalid header format"),
+        ] {
+            let path = temp_file("headers-file-error", &format!("# first\n{content}"));
+            let err = parse_headers_file(&path).unwrap_err().to_string();
+            let _ = std::fs::remove_file(&path);
+            assert!(err.contains(reason), "{err}");
+        }
+    }
+
+    #[test]
+    fn quic_version_is_1_or_reserved() {
+        assert_eq!(parse_quic_version("1").unwrap(), quiche::PROTOCOL_VERSION);
+        assert_eq!(parse_quic_version("0x1").unwrap(), quiche::PROTOCOL_VERSION);
+        assert_eq!(parse_quic_version("0x1a2a3a4a").unwrap(), 0x1a2a_3a4a);
+        assert_eq!(parse_quic_version("babababa").unwrap(), 0xbaba_baba);
+
+        let err = parse_quic_version("0xff00001d").unwrap_err().to_string();
+        assert!(err.contains("unsupported QUIC version 0xff00001d"), "{err}");
+        let err = parse_quic_version("v1").unwrap_err().to_string();
+        assert!(err.contains("invalid QUIC version v1"), "{err}");
+    }
+
+    #[test]
+    fn form_fields_are_text_or_files() {
+        let path = temp_file("form.bin", "\x01data");
+        let filename = std::path::Path::new(&path).file_name().unwrap();
+        let fields = [
+            "q=FUZZ".to_string(),
+            "eq=a=b".to_string(),
+            format!("upload=@{path}"),
+        ];
+        let form = parse_form(&fields, &mut seeded_rng(Some(1), RNG_FORM)).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let body = String::from_utf8(form.encode("admin")).unwrap();
+        assert!(body.contains("name=\"q\"\r\n\r\nadmin\r\n"), "{body}");
+        assert!(body.contains("name=\"eq\"\r\n\r\na=b\r\n"), "{body}");
+        let upload = format!("name=\"upload\"; filename=\"{}\"", filename.display());
+        assert!(body.contains(&upload), "{body}");
+        assert!(body.contains("\r\n\r\n\x01data\r\n"), "{body}");
+    }
+
+    #[test]
+    fn form_field_errors() {
+        let mut rng = seeded_rng(Some(1), RNG_FORM);
+        for (field, reason) in [
+            ("=value", "expected NAME=VALUE"),
+            ("novalue", "expected NAME=VALUE"),
+            ("f=@/nonexistent/fuzzh3", "failed to read form file"),
+        ] {
+            let err = parse_form(&[field.to_string()], &mut rng).unwrap_err();
+            assert!(err.to_string().contains(reason), "{err}");
+        }
+    }
+}
@@ -1,4 +1,3 @@
 fn main() -> anyhow::Result<()> {
-    env_logger::init();
     fuzzh3::run()
 }
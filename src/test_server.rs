@@ -0,0 +1,202 @@
+// HTTP/3 server on a loopback port, for tests which run whole scans against it
+use quiche::h3::NameValue;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cert.crt");
+const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cert.key");
+
+// Longest wait for a packet, so the stop flag is noticed soon
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// Answer to a request: status and extra headers, the body is always "ok"
+pub type Route = fn(&str) -> (u16, Vec<(&'static str, String)>);
+
+pub struct TestServer {
+    pub port: u16,
+    requests: Arc<Mutex<Vec<String>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Conn {
+    quic: quiche::Connection,
+    h3: Option<quiche::h3::Connection>,
+    answered: usize,
+    goaway: Option<u64>, // first stream id left unprocessed
+}
+
+impl TestServer {
+    // Answers every request with `route`. With `goaway_after` each connection sends GOAWAY
+    // after that many requests and drops the ones which arrive later
+    pub fn start(route: Route, goaway_after: Option<usize>) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let requests = Arc::clone(&requests);
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || serve(socket, route, goaway_after, &requests, &stop))
+        };
+
+        Self {
+            port,
+            requests,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("https://127.0.0.1:{}{path}", self.port)
+    }
+
+    // Paths of the requests answered so far, in the order they arrived
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve(
+    socket: UdpSocket,
+    route: Route,
+    goaway_after: Option<usize>,
+    requests: &Mutex<Vec<String>>,
+    stop: &AtomicBool,
+) {
+    let local = socket.local_addr().unwrap();
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+    config.load_cert_chain_from_pem_file(CERT).unwrap();
+    config.load_priv_key_from_pem_file(KEY).unwrap();
+    config
+        .set_application_protos(quiche::h3::APPLICATION_PROTOCOL)
+        .unwrap();
+    config.set_max_idle_timeout(5000);
+    config.set_initial_max_data(10_000_000);
+    config.set_initial_max_stream_data_bidi_remote(1_000_000);
+    config.set_initial_max_stream_data_uni(1_000_000);
+    config.set_initial_max_streams_bidi(100);
+    config.set_initial_max_streams_uni(100);
+
+    let mut conns: Vec<Conn> = Vec::new();
+    // both the client's first destination id and the one we issued lead to a connection
+    let mut ids: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut buf = [0; 65535];
+    let mut out = [0; 1500];
+
+    while !stop.load(Ordering::Relaxed) {
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                let Ok(hdr) = quiche::Header::from_slice(&mut buf[..len], quiche::MAX_CONN_ID_LEN)
+                else {
+                    continue;
+                };
+                let index = match ids.get(hdr.dcid.as_ref()) {
+                    Some(index) => *index,
+                    None if hdr.ty == quiche::Type::Initial => {
+                        let mut scid = [0; quiche::MAX_CONN_ID_LEN];
+                        rand::rng().fill_bytes(&mut scid);
+                        let scid = quiche::ConnectionId::from_ref(&scid);
+                        let quic = quiche::accept(&scid, None, local, from, &mut config).unwrap();
+                        ids.insert(hdr.dcid.to_vec(), conns.len());
+                        ids.insert(scid.to_vec(), conns.len());
+                        conns.push(Conn {
+                            quic,
+                            h3: None,
+                            answered: 0,
+                            goaway: None,
+                        });
+                        conns.len() - 1
+                    }
+                    None => continue,
+                };
+                let info = quiche::RecvInfo { from, to: local };
+                let _ = conns[index].quic.recv(&mut buf[..len], info);
+            }
+            Err(_) => conns.iter_mut().for_each(|conn| conn.quic.on_timeout()),
+        }
+
+        for conn in &mut conns {
+            handle(conn, route, goaway_after, requests);
+            while let Ok((len, info)) = conn.quic.send(&mut out) {
+                let _ = socket.send_to(&out[..len], info.to);
+            }
+        }
+    }
+}
+
+fn handle(
+    conn: &mut Conn,
+    route: Route,
+    goaway_after: Option<usize>,
+    requests: &Mutex<Vec<String>>,
+) {
+    if conn.h3.is_none() && conn.quic.is_established() {
+        let config = quiche::h3::Config::new().unwrap();
+        conn.h3 = quiche::h3::Connection::with_transport(&mut conn.quic, &config).ok();
+    }
+    let Some(h3) = conn.h3.as_mut() else {
+        return;
+    };
+
+    while let Ok((id, event)) = h3.poll(&mut conn.quic) {
+        let quiche::h3::Event::Headers { list, .. } = event else {
+            continue;
+        };
+        if conn.goaway.is_some_and(|goaway| id >= goaway) {
+            continue;
+        }
+        let path = list
+            .iter()
+            .find(|h| h.name() == b":path")
+            .map(|h| String::from_utf8_lossy(h.value()).to_string())
+            .unwrap_or_default();
+
+        let (status, extra) = route(&path);
+        let mut headers = vec![quiche::h3::Header::new(
+            b":status",
+            status.to_string().as_bytes(),
+        )];
+        for (name, value) in &extra {
+            headers.push(quiche::h3::Header::new(name.as_bytes(), value.as_bytes()));
+        }
+        if h3
+            .send_response(&mut conn.quic, id, &headers, false)
+            .is_err()
+        {
+            continue;
+        }
+        let _ = h3.send_body(&mut conn.quic, id, b"ok", true);
+        requests.lock().unwrap().push(path);
+
+        conn.answered += 1;
+        if conn.goaway.is_none() && goaway_after.is_some_and(|after| conn.answered >= after) {
+            conn.goaway = Some(id + 4);
+            let _ = h3.send_goaway(&mut conn.quic, id + 4);
+        }
+    }
+}
+
+// Writes `words` one per line to a fresh file and returns its path
+pub fn wordlist(name: &str, words: &[String]) -> String {
+    let path = std::env::temp_dir().join(format!("fuzzh3-{name}-{}.txt", std::process::id()));
+    std::fs::write(&path, words.join("\n")).unwrap();
+    path.to_string_lossy().to_string()
+}